@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use avian2d::prelude::{AngularVelocity, LinearVelocity};
+use bevy::prelude::Transform;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use poc::{
+    algebraic::RandomAlgebraic,
+    bench::{AlgebraicFixture, RollbackFixture},
+    Grounded,
+};
+
+const ENTITIES: [usize; 3] = [1, 4, 16];
+const INPUTS: [usize; 4] = [1, 4, 16, 64];
+const HISTORY: [usize; 3] = [4, 16, 64];
+
+fn rollback(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rollback");
+    group.sample_size(20);
+
+    for entities in ENTITIES {
+        for inputs in INPUTS {
+            let mut fixture = RollbackFixture::new(entities, inputs);
+
+            group.bench_with_input(
+                BenchmarkId::new(format!("{} entities", entities), inputs),
+                &inputs,
+                |b, _| b.iter(|| fixture.rollback()),
+            );
+        }
+    }
+
+    group.finish();
+}
+
+fn algebraic_correction<C: RandomAlgebraic>(c: &mut Criterion, name: &str) {
+    let mut group = c.benchmark_group(format!("algebraic/{}", name));
+
+    for entities in ENTITIES {
+        for history in HISTORY {
+            let mut fixture = AlgebraicFixture::<C>::new(entities, history);
+
+            group.bench_with_input(
+                BenchmarkId::new(format!("{} entities", entities), history),
+                &history,
+                |b, _| {
+                    // Only the correction is measured, refilling consumed values is not.
+                    b.iter_custom(|iters| {
+                        let mut total = Duration::ZERO;
+
+                        for _ in 0..iters {
+                            fixture.refill();
+
+                            let start = Instant::now();
+                            fixture.correct();
+                            total += start.elapsed();
+                        }
+
+                        total
+                    })
+                },
+            );
+        }
+    }
+
+    group.finish();
+}
+
+fn algebraic(c: &mut Criterion) {
+    algebraic_correction::<LinearVelocity>(c, "linear_velocity");
+    algebraic_correction::<AngularVelocity>(c, "angular_velocity");
+    algebraic_correction::<Transform>(c, "transform");
+    algebraic_correction::<Grounded>(c, "grounded");
+}
+
+criterion_group!(benches, rollback, algebraic);
+criterion_main!(benches);
@@ -0,0 +1,182 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc::{self, Sender},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use crossterm::event::{self, KeyCode};
+
+const SERVER_LISTENING: &str = "Listening on";
+const SERVER_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A child process whose output is prefixed with its role.
+struct Process {
+    role: String,
+    child: Child,
+    status: Option<ExitStatus>,
+}
+
+impl Process {
+    fn spawn(
+        role: String,
+        executable: &Path,
+        args: &[String],
+        listening: Option<Sender<()>>,
+    ) -> Result<Self> {
+        let mut child = Command::new(executable)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        forward_output(role.clone(), child.stdout.take().unwrap(), listening);
+        forward_output(role.clone(), child.stderr.take().unwrap(), None);
+
+        println!("[launcher] Started {}: {}", role, args.join(" "));
+
+        Ok(Self {
+            role,
+            child,
+            status: None,
+        })
+    }
+
+    fn poll(&mut self) -> Option<ExitStatus> {
+        if self.status.is_none() {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                println!("[launcher] {} exited with {}", self.role, status);
+                self.status = Some(status);
+            }
+        }
+
+        self.status
+    }
+
+    fn shutdown(&mut self) {
+        if self.poll().is_some() {
+            return;
+        }
+
+        let _ = self.child.kill();
+
+        if let Ok(status) = self.child.wait() {
+            println!("[launcher] {} killed, exited with {}", self.role, status);
+            self.status = Some(status);
+        }
+    }
+}
+
+fn forward_output(role: String, output: impl Read + Send + 'static, listening: Option<Sender<()>>) {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+
+            if let Some(listening) = &listening {
+                if line.starts_with(SERVER_LISTENING) {
+                    let _ = listening.send(());
+                }
+            }
+
+            println!("[{}] {}", role, line);
+        }
+    });
+}
+
+/// Starts a server and one client per entry in `clients`. Each entry holds the
+/// arguments passed to that client after the `client` subcommand. Runs until
+/// enter is pressed, the server exits or all clients have exited.
+pub fn launch(
+    executable: &Path,
+    shared_args: &[String],
+    server_args: &[String],
+    clients: &[Vec<String>],
+) -> Result<()> {
+    let (sender_listening, receiver_listening) = mpsc::channel();
+
+    // Individual arguments come last so they override the shared ones.
+    let args = ["server".to_string()]
+        .into_iter()
+        .chain(shared_args.iter().cloned())
+        .chain(server_args.iter().cloned())
+        .collect::<Vec<_>>();
+
+    let mut server = Process::spawn(
+        "server".to_string(),
+        executable,
+        &args,
+        Some(sender_listening),
+    )?;
+
+    if receiver_listening
+        .recv_timeout(SERVER_STARTUP_TIMEOUT)
+        .is_err()
+    {
+        server.shutdown();
+        bail!("Server did not start listening");
+    }
+
+    let mut processes = Vec::new();
+
+    for (index, client_args) in clients.iter().enumerate() {
+        let args = ["client".to_string()]
+            .into_iter()
+            .chain(shared_args.iter().cloned())
+            .chain(client_args.iter().cloned())
+            .collect::<Vec<_>>();
+
+        match Process::spawn(format!("client-{}", index), executable, &args, None) {
+            Ok(process) => processes.push(process),
+            Err(err) => println!("[launcher] Failed to start client-{}: {}", index, err),
+        }
+    }
+
+    loop {
+        if event::poll(Duration::from_millis(100))? {
+            if let event::Event::Key(event::KeyEvent { code, kind, .. }) = event::read()? {
+                if kind == event::KeyEventKind::Press && code == KeyCode::Enter {
+                    println!("[launcher] Closing: Polled enter");
+                    break;
+                }
+            }
+        } else {
+            let mut clients_alive = false;
+
+            for process in &mut processes {
+                clients_alive |= process.poll().is_none();
+            }
+
+            if server.poll().is_some() {
+                println!("[launcher] Closing: Server dead");
+                break;
+            }
+
+            if !clients_alive {
+                println!("[launcher] Closing: All clients dead");
+                break;
+            }
+        }
+    }
+
+    for process in &mut processes {
+        process.shutdown();
+    }
+
+    server.shutdown();
+
+    println!("[launcher] Exit codes:");
+
+    for process in processes.iter().chain([&server]) {
+        match process.status.and_then(|status| status.code()) {
+            Some(code) => println!("[launcher]   {}: {}", process.role, code),
+            None => println!("[launcher]   {}: terminated by signal", process.role),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,3 @@
+mod poc;
+
+pub use poc::*;
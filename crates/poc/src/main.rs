@@ -2,7 +2,8 @@ use std::{
     cell::RefCell,
     env,
     io::{stdin, stdout, Read, Write},
-    net::Ipv4Addr,
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
     process::{self, Command},
     rc::Rc,
     str::FromStr,
@@ -10,93 +11,446 @@ use std::{
     thread,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bevy::{app::PluginsState, prelude::*, tasks::tick_global_task_pools_on_main_thread};
 use bevy_quinnet::{
     client::connection::ClientEndpointConfiguration, server::ServerEndpointConfiguration,
 };
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use crossterm::{
     event::{self, Event, KeyCode, KeyEvent},
     terminal::{disable_raw_mode, enable_raw_mode},
 };
-use poc::PocPlugin;
+use poc::{
+    conditioner::Distribution, config::NetworkConfig, experiment::ExperimentMatrix,
+    lag_compensation::LagCompensationConfig, level::Level, recording::Recorder,
+    remote::RemoteBodies, trace::Tracer, tuning::MovementTuning, ClientMode, PocPlugin,
+    Reconciliation, SimulationMode,
+};
 use std::io;
 use std::sync::mpsc;
 use std::time::Duration;
 
-mod poc;
+mod launcher;
 
-fn main() -> anyhow::Result<()> {
-    if let Some(argument) = env::args().nth(1) {
-        match argument.as_str() {
-            "client" => run_client(),
-            "server" => run_server(),
-            _ => panic!("Unknown arguments: {}", argument),
+#[derive(Parser)]
+#[command(args_override_self = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+
+    #[command(flatten)]
+    network: NetworkArgs,
+
+    /// Must be the same for the server and all clients.
+    #[arg(long, global = true, value_enum, default_value_t = SimulationMode::Standard)]
+    simulation: SimulationMode,
+
+    #[command(flatten)]
+    launch: LaunchArgs,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    Client {
+        #[arg(value_enum, default_value_t = ClientMode::Manual)]
+        mode: ClientMode,
+
+        #[arg(long, value_enum, default_value_t = Reconciliation::Rollback)]
+        reconciliation: Reconciliation,
+
+        /// How the players of other clients collide with the predicted player.
+        #[arg(long, value_enum, default_value_t = RemoteBodies::Interpolated)]
+        remote_bodies: RemoteBodies,
+
+        /// Traces inputs, predictions, server states and corrections to this file.
+        #[arg(long)]
+        trace: Option<PathBuf>,
+    },
+    Server {
+        /// Records all inputs and periodic snapshots to this file.
+        #[arg(long)]
+        record: Option<PathBuf>,
+
+        /// Ticks between two snapshots in the recording.
+        #[arg(long, default_value_t = 30)]
+        snapshot_interval: u32,
+
+        /// Movement presets file in the assets directory, reloaded when it changes.
+        #[arg(long)]
+        movement: Option<String>,
+
+        /// Preset from the `--movement` file used for all players.
+        #[arg(long, default_value = "default")]
+        movement_preset: String,
+
+        /// RON file containing a `Level`, e.g. `assets/levels/slopes.ron`.
+        #[arg(long)]
+        level: Option<PathBuf>,
+
+        /// Maximum ticks hit detection rewinds colliders for lagging clients.
+        #[arg(long, default_value_t = 20)]
+        max_rewind_ticks: u32,
+
+        /// Ticks clients with the default `--remote-bodies interpolated` show
+        /// remote players behind their prediction. Hit detection rewinds by the same amount.
+        #[arg(long, default_value_t = 6)]
+        interpolation_ticks: u32,
+    },
+    /// Re-runs a recording headlessly and compares it with the recorded snapshots.
+    Replay {
+        path: PathBuf,
+
+        /// Maximum difference in position and velocity that is not reported.
+        #[arg(long, default_value_t = 0.5)]
+        tolerance: f32,
+    },
+    /// Lines up a client trace with a server recording by tick.
+    Align {
+        trace: PathBuf,
+        recording: PathBuf,
+
+        /// Prediction errors larger than this are marked as divergence.
+        #[arg(long, default_value_t = 0.5)]
+        tolerance: f32,
+    },
+    /// Writes a CSV and an SVG chart of corrections, replays and RTT of client traces.
+    Analyze {
+        #[arg(required = true)]
+        traces: Vec<PathBuf>,
+
+        /// Directory the CSV and SVG are written to.
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Runs headless sessions over the loopback transport for every combination
+    /// of the given values and compares the reconciliation strategies.
+    Experiment {
+        #[arg(long, value_enum, value_delimiter = ',', default_values_t = [Reconciliation::Rollback, Reconciliation::Algebraic])]
+        strategies: Vec<Reconciliation>,
+
+        /// One way latencies in milliseconds.
+        #[arg(long, value_delimiter = ',', default_values_t = [0.0, 50.0, 100.0])]
+        latencies: Vec<f32>,
+
+        #[arg(long, value_delimiter = ',', default_values_t = [0.0, 0.05])]
+        losses: Vec<f32>,
+
+        #[arg(long, value_delimiter = ',', default_values_t = [30.0])]
+        tick_rates: Vec<f32>,
+
+        /// Numbers of bot clients, each predicting its own player.
+        #[arg(long, value_delimiter = ',', default_values_t = [1, 4])]
+        clients: Vec<usize>,
+
+        /// Ticks per run.
+        #[arg(long, default_value_t = 600)]
+        ticks: usize,
+
+        /// CSV file for the results.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+}
+
+/// Used when no subcommand is given to start a server and several clients.
+#[derive(Args)]
+struct LaunchArgs {
+    /// Number of clients started with default arguments, ignored if `--client` is given.
+    #[arg(long, default_value_t = 1)]
+    clients: usize,
+
+    /// Arguments of a single client, e.g. `--client "bot --latency 100"`. Repeat for more clients.
+    /// Quote arguments containing spaces, e.g. `--client "--trace 'my trace.trace'"`.
+    #[arg(long = "client", value_name = "ARGS", allow_hyphen_values = true)]
+    client_args: Vec<String>,
+
+    /// Arguments of the server, e.g. `--server "--loss 0.1"`. Quote arguments
+    /// containing spaces, e.g. `--server "--record 'my session.rec'"`.
+    #[arg(long = "server", value_name = "ARGS", allow_hyphen_values = true)]
+    server_args: Option<String>,
+}
+
+impl LaunchArgs {
+    fn clients(&self) -> Vec<Vec<String>> {
+        if self.client_args.is_empty() {
+            vec![Vec::new(); self.clients]
+        } else {
+            self.client_args
+                .iter()
+                .map(|args| split_args(args))
+                .collect()
         }
-    } else {
-        // enable_raw_mode()?;
-
-        let filename = env::current_exe()?;
-
-        /*
-               let mut client1 = Command::new(&filename)
-                   .arg("client")
-                   .arg("automatic")
-                   .stdout(stdout())
-                   .spawn()?;
-        */
-        let mut client2 = Command::new(&filename)
-            .arg("client")
-            .stdout(stdout())
-            .spawn()?;
-
-        let mut server = Command::new(&filename)
-            .arg("server")
-            .stdout(stdout())
-            .spawn()?;
-
-        loop {
-            if event::poll(Duration::from_millis(100))? {
-                match event::read()? {
-                    event::Event::Key(event::KeyEvent { code, kind, .. }) => {
-                        if kind != event::KeyEventKind::Press {
-                            continue;
-                        }
-
-                        if code == KeyCode::Enter {
-                            println!("Closing: Polled enter");
-                            break;
-                        }
-                    }
-                    _ => {}
-                }
-            } else {
-                /*if client1.try_wait().map(|x| x.is_some()).unwrap_or(true) {
-                    println!("Closing: Client1 dead");
-                    break;
-                }*/
-
-                if client2.try_wait().map(|x| x.is_some()).unwrap_or(true) {
-                    println!("Closing: Client2 dead");
-                    break;
-                }
+    }
+}
+
+/// Splits at whitespace outside of single or double quotes, so arguments such
+/// as `--record "my session.rec"` keep their spaces. There are no escapes.
+fn split_args(args: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = None::<String>;
+    let mut quote = None;
+
+    for c in args.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                current.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => result.extend(current.take()),
+            (_, c) => current.get_or_insert_default().push(c),
+        }
+    }
+
+    result.extend(current);
+    result
+}
+
+#[derive(Args)]
+struct NetworkArgs {
+    /// RON file containing a `NetworkConfig`.
+    #[arg(long, global = true, env = "POC_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address the server listens on, e.g. `0.0.0.0:24325` or `[::]:24325`.
+    #[arg(long, global = true, env = "POC_BIND", value_parser = parse_address)]
+    bind: Option<SocketAddr>,
+
+    /// Address of the server the client connects to. Host names are resolved.
+    #[arg(long, global = true, env = "POC_CONNECT", value_parser = parse_address)]
+    connect: Option<SocketAddr>,
 
-                if server.try_wait().map(|x| x.is_some()).unwrap_or(true) {
-                    println!("Closing: Server dead");
-                    break;
+    /// Simulated one way latency in milliseconds for incoming and outgoing messages.
+    #[arg(long, global = true, env = "POC_LATENCY")]
+    latency: Option<f32>,
+
+    /// Standard deviation of the simulated latency in milliseconds.
+    #[arg(long, global = true, env = "POC_JITTER")]
+    jitter: Option<f32>,
+
+    /// Probability of dropping a message on unreliable channels.
+    #[arg(long, global = true, env = "POC_LOSS")]
+    loss: Option<f32>,
+
+    /// Probability of duplicating a message on unreliable channels.
+    #[arg(long, global = true, env = "POC_DUPLICATE")]
+    duplicate: Option<f32>,
+
+    /// Probability of delaying a message by another latency so it gets reordered.
+    #[arg(long, global = true, env = "POC_REORDER")]
+    reorder: Option<f32>,
+
+    /// Seed of the network conditioner.
+    #[arg(long, global = true, env = "POC_SEED")]
+    seed: Option<u64>,
+}
+
+impl NetworkArgs {
+    fn network_config(&self) -> Result<NetworkConfig> {
+        let mut config = match &self.config {
+            Some(path) => NetworkConfig::from_file(path)?,
+            None => NetworkConfig::default(),
+        };
+
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+
+        if let Some(connect) = self.connect {
+            config.connect = connect;
+        }
+
+        let conditions = &mut config.conditioner.default;
+
+        match (self.latency, self.jitter) {
+            (Some(latency), Some(jitter)) => {
+                conditions.latency = Distribution::Normal {
+                    mean: latency,
+                    std_dev: jitter,
                 }
             }
+            (Some(latency), None) => conditions.latency = Distribution::Constant(latency),
+            (None, Some(_)) => bail!("--jitter requires --latency"),
+            (None, None) => {}
         }
 
-        let _ = server.kill();
-        //let _ = client1.kill();
-        let _ = client2.kill();
+        if let Some(reorder) = self.reorder {
+            conditions.reorder = reorder;
 
-        Ok(())
+            // Keeps a delay given in the config file.
+            if conditions.reorder_delay.is_zero() {
+                conditions.reorder_delay = conditions.latency;
+            }
+        }
+
+        if let Some(loss) = self.loss {
+            conditions.loss = loss;
+        }
+
+        if let Some(duplicate) = self.duplicate {
+            conditions.duplicate = duplicate;
+        }
+
+        if let Some(seed) = self.seed {
+            config.conditioner.seed = Some(seed);
+        }
+
+        Ok(config)
+    }
+
+    fn forward(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(config) = &self.config {
+            args.push("--config".to_string());
+            args.push(config.display().to_string());
+        }
+
+        if let Some(bind) = self.bind {
+            args.push("--bind".to_string());
+            args.push(bind.to_string());
+        }
+
+        if let Some(connect) = self.connect {
+            args.push("--connect".to_string());
+            args.push(connect.to_string());
+        }
+
+        let conditions = [
+            ("--latency", self.latency),
+            ("--jitter", self.jitter),
+            ("--loss", self.loss),
+            ("--duplicate", self.duplicate),
+            ("--reorder", self.reorder),
+        ];
+
+        for (name, value) in conditions {
+            if let Some(value) = value {
+                args.push(name.to_string());
+                args.push(value.to_string());
+            }
+        }
+
+        if let Some(seed) = self.seed {
+            args.push("--seed".to_string());
+            args.push(seed.to_string());
+        }
+
+        args
+    }
+}
+
+fn parse_address(address: &str) -> Result<SocketAddr, String> {
+    address
+        .to_socket_addrs()
+        .map_err(|err| format!("Invalid address {}: {}", address, err))?
+        .next()
+        .ok_or_else(|| format!("Address {} did not resolve", address))
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    if let Some(command) = cli.command {
+        let network = cli.network.network_config()?;
+
+        match command {
+            CliCommand::Client {
+                mode,
+                reconciliation,
+                remote_bodies,
+                trace,
+            } => run_client(
+                mode,
+                reconciliation,
+                remote_bodies,
+                cli.simulation,
+                network,
+                trace.map(|path| Tracer::create(&path)).transpose()?,
+            ),
+            CliCommand::Server {
+                record,
+                snapshot_interval,
+                movement,
+                movement_preset,
+                level,
+                max_rewind_ticks,
+                interpolation_ticks,
+            } => run_server(
+                cli.simulation,
+                network,
+                record
+                    .map(|path| Recorder::create(&path, snapshot_interval))
+                    .transpose()?,
+                movement.map(|path| MovementTuning {
+                    path,
+                    preset: movement_preset,
+                }),
+                level.map(|path| Level::from_file(&path)).transpose()?,
+                LagCompensationConfig {
+                    max_rewind_ticks,
+                    interpolation_ticks,
+                },
+            ),
+            CliCommand::Replay { path, tolerance } => run_replay(&path, tolerance),
+            CliCommand::Align {
+                trace,
+                recording,
+                tolerance,
+            } => poc::trace::align(&trace, &recording, tolerance),
+            CliCommand::Analyze { traces, out } => poc::analysis::analyze(&traces, &out),
+            CliCommand::Experiment {
+                strategies,
+                latencies,
+                losses,
+                tick_rates,
+                clients,
+                ticks,
+                out,
+            } => {
+                let matrix = ExperimentMatrix {
+                    strategies,
+                    latencies,
+                    losses,
+                    tick_rates,
+                    clients,
+                    ticks,
+                    seed: network.conditioner.seed.unwrap_or(0),
+                };
+
+                poc::experiment::run(&matrix, out.as_deref()).map(|_| ())
+            }
+        }
+    } else {
+        let mut shared_args = cli.network.forward();
+
+        if let Some(simulation) = cli.simulation.to_possible_value() {
+            shared_args.push("--simulation".to_string());
+            shared_args.push(simulation.get_name().to_string());
+        }
+
+        launcher::launch(
+            &env::current_exe()?,
+            &shared_args,
+            &cli.launch
+                .server_args
+                .as_deref()
+                .map(split_args)
+                .unwrap_or_default(),
+            &cli.launch.clients(),
+        )
     }
 }
 
-fn run_client() -> Result<()> {
+fn run_client(
+    mode: ClientMode,
+    reconciliation: Reconciliation,
+    remote_bodies: RemoteBodies,
+    simulation: SimulationMode,
+    network: NetworkConfig,
+    tracer: Option<Tracer>,
+) -> Result<()> {
     let mut app = App::new();
 
     app.add_plugins(DefaultPlugins.set(WindowPlugin {
@@ -107,32 +461,83 @@ fn run_client() -> Result<()> {
         ..default()
     }))
     .add_plugins(PocPlugin {
-        typ: poc::PocType::Client(
-            env::args()
-                .nth(2)
-                .map(|x| x == "automatic")
-                .unwrap_or(false),
-        ),
+        typ: poc::PocType::Client(mode),
+        transport: poc::Transport::Quinnet,
+        network,
     })
-    .run();
+    .insert_resource(reconciliation)
+    .insert_resource(remote_bodies)
+    .insert_resource(simulation);
+
+    if let Some(tracer) = tracer {
+        app.insert_resource(tracer);
+    }
+
+    app.run();
 
     Ok(())
 }
 
-fn run_server() -> Result<()> {
+fn run_server(
+    simulation: SimulationMode,
+    network: NetworkConfig,
+    recorder: Option<Recorder>,
+    tuning: Option<MovementTuning>,
+    level: Option<Level>,
+    lag_compensation: LagCompensationConfig,
+) -> Result<()> {
     let mut app = App::new();
 
-    app.add_plugins(DefaultPlugins.set(WindowPlugin {
-        primary_window: Some(Window {
-            title: "Server".to_string(),
-            ..Default::default()
-        }),
-        ..default()
-    }))
+    app.add_plugins(
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    title: "Server".to_string(),
+                    ..Default::default()
+                }),
+                ..default()
+            })
+            .set(AssetPlugin {
+                watch_for_changes_override: Some(true),
+                ..default()
+            }),
+    )
     .add_plugins(PocPlugin {
         typ: poc::PocType::Server,
+        transport: poc::Transport::Quinnet,
+        network,
     })
-    .run();
+    .insert_resource(simulation)
+    .insert_resource(lag_compensation);
+
+    if let Some(recorder) = recorder {
+        app.insert_resource(recorder);
+    }
+
+    if let Some(tuning) = tuning {
+        app.insert_resource(tuning);
+    }
+
+    if let Some(level) = level {
+        app.insert_resource(level);
+    }
+
+    app.run();
+
+    Ok(())
+}
+
+fn run_replay(path: &Path, tolerance: f32) -> Result<()> {
+    let report = poc::recording::replay(path, tolerance)?;
+
+    println!(
+        "Replayed {} ticks and {} snapshots, max position error {}, max velocity error {}",
+        report.ticks, report.snapshots, report.max_position_error, report.max_velocity_error
+    );
+
+    if let Some(tick) = report.first_divergence {
+        bail!("Replay diverged from the recording at tick {}", tick);
+    }
 
     Ok(())
 }
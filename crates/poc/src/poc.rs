@@ -1,6 +1,6 @@
 use std::{
     collections::VecDeque,
-    net::{Ipv4Addr, Ipv6Addr},
+    mem,
     ops::{DerefMut, Sub},
     time::{Duration, Instant},
 };
@@ -10,12 +10,13 @@ use avian2d::{
     prelude::{
         AngularVelocity, Collider, Friction, Gravity, LinearDamping, LinearVelocity, Physics,
         PhysicsInterpolationPlugin, PhysicsSchedule, PhysicsSchedulePlugin, RigidBody,
-        TransformInterpolation,
+        SolverConfig, SubstepCount, TransformInterpolation,
     },
     sync::SyncPlugin,
     PhysicsPlugins,
 };
 use bevy::{
+    asset::AssetPlugin,
     core_pipeline::core_2d::graph::input,
     ecs::{component::Mutable, schedule::ScheduleLabel},
     math::ops::sin,
@@ -27,21 +28,19 @@ use bevy_defer::{AsyncAccess, AsyncCommandsExtension, AsyncPlugin, AsyncWorld};
 use bevy_quinnet::{
     client::{
         certificate::CertificateVerificationMode,
-        client_just_connected,
         connection::{ClientEndpointConfiguration, ConnectionEvent, ConnectionFailedEvent},
         QuinnetClient,
     },
     server::{certificate::CertificateRetrievalMode, QuinnetServer, ServerEndpointConfiguration},
     shared::channels::ChannelsConfiguration,
 };
-use bevy_quinnet::client::client_connected;
 use bevy_replicon::{
     client::{confirm_history::ConfirmHistory, ClientPlugin, ClientSet},
     prelude::{
-        server_running, AppMarkerExt, AppRuleExt, Channel, ClientEventAppExt, ClientEventPlugin,
-        ClientTriggerAppExt, ClientTriggerExt, ClientVisibility, ConnectedClient, FromClient,
-        Replicated, RepliconChannels, RepliconClient, ServerEventPlugin, ServerTriggerAppExt,
-        SyncRelatedAppExt,
+        client_connected, client_just_connected, server_running, AppMarkerExt, AppRuleExt,
+        Channel, ClientEventAppExt, ClientEventPlugin, ClientTriggerAppExt, ClientTriggerExt,
+        ClientVisibility, ConnectedClient, FromClient, Replicated, RepliconChannels,
+        RepliconClient, RepliconServer, ServerEventPlugin, ServerTriggerAppExt, SyncRelatedAppExt,
     },
     server::{
         server_tick::ServerTick, ReplicatedClient, ServerPlugin, TickPolicy, VisibilityPolicy,
@@ -61,94 +60,342 @@ use bevy_replicon_quinnet::{
     client::RepliconQuinnetClientPlugin, server::RepliconQuinnetServerPlugin,
     ChannelsConfigurationExt, RepliconQuinnetPlugins,
 };
+use algebraic::{
+    system_algebraic_correct, system_algebraic_snapshot, system_record_prediction,
+    system_discard_acks, Algebraic,
+};
+use conditioner::ConditionerPlugin;
+use config::NetworkConfig;
 use interpolation::{client_received_replication, Interpolation, InterpolationPlugin};
-use movement::{Movement, MovementConfig, MovementController, MovementInput, MovementPlugin};
+use lag_compensation::{system_record_collider_history, LagCompensationConfig};
+use level::{Level, SpawnPoints, TerrainShape};
+use movement::{Movement, MovementController, MovementPlugin};
+use platform::{MovingPlatform, PlatformPlugin, SimulationTick};
+use prediction::{system_init_prediction_world, PredictionWorld};
+use recording::{system_record_header, system_record_snapshot, system_record_tick, Recorder};
+use projectile::{Projectile, ProjectilePlugin, PROJECTILE_SPEED};
+use remote::{system_update_remote_lag, RemoteBodies, RemoteBodyPlugin, RemotePoses};
+use spawn::{LocalSpawns, PredictedSpawn, SpawnPlugin, SpawnRequest};
+use trace::{system_trace_acks, system_trace_correction, system_trace_tick, Tracer};
+use tuning::TuningPlugin;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::poc::movement::Grounded;
+pub use movement::{
+    Grounded, JumpState, MovementConfig, MovementInput, ServerMovementConfig, WallContact,
+};
+
+/// Calls `$macro` with the components corrected by [`Reconciliation::Algebraic`],
+/// so their registration and the group law tests in [`algebraic`] share one list.
+macro_rules! algebraic_components {
+    ($macro:ident) => {
+        $macro!(
+            linear_velocity: LinearVelocity,
+            angular_velocity: AngularVelocity,
+            transform: Transform,
+            grounded: Grounded
+        );
+    };
+}
 
+pub mod algebraic;
+pub mod analysis;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod conditioner;
+pub mod config;
+pub mod experiment;
 mod interpolation;
+pub mod lag_compensation;
+pub mod level;
+pub mod loopback;
 mod movement;
+pub mod platform;
+pub mod prediction;
+pub mod projectile;
+pub mod recording;
+pub mod remote;
+pub mod spawn;
+pub mod trace;
+pub mod tuning;
+#[cfg(test)]
+mod tests;
 
 pub struct PocPlugin {
     pub typ: PocType,
+    pub transport: Transport,
+    pub network: NetworkConfig,
+}
+
+pub enum Transport {
+    /// QUIC connection using the addresses from [`NetworkConfig`].
+    Quinnet,
+    /// Messages are moved between apps by a [`loopback::LoopbackNetwork`].
+    Loopback,
 }
 
 pub enum PocType {
-    Client(bool),
+    Client(ClientMode),
     Server,
 }
 
-const PORT: u16 = 24325;
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientMode {
+    /// Keyboard input with prediction.
+    Manual,
+    /// Sends a sine wave as input without predicting it.
+    Automatic,
+    /// Random inputs with prediction.
+    Bot,
+    /// Replays the [`InputScript`] resource with prediction.
+    #[value(skip)]
+    Scripted,
+}
+
+/// How the client corrects its prediction when a server state arrives.
+#[derive(
+    Resource, clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+pub enum Reconciliation {
+    /// Resets to the server state and replays all newer inputs.
+    #[default]
+    Rollback,
+    /// Adds the difference between server state and prediction, see [`Algebraic`].
+    /// Predicted components registered with `replicate_rollback_only` keep
+    /// their predicted value.
+    Algebraic,
+}
+
+/// How [`Simulate`] is stepped on client and server.
+#[derive(
+    Resource, clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+pub enum SimulationMode {
+    /// Steps by the delta of the current `Time`.
+    #[default]
+    Standard,
+    /// Steps by exactly [`tick_duration`] of the tick rate in [`IndividualServerConfig`]
+    /// with a fixed number of substeps and without warm starting, so client and
+    /// server compute bit-identical states for the same inputs. Build with the
+    /// `deterministic` feature to also get the same results across platforms.
+    Deterministic,
+}
+
+/// Solver substeps per tick in [`SimulationMode::Deterministic`].
+const DETERMINISTIC_SUBSTEPS: u32 = 6;
+
+/// Length of a tick, computed the same way as `Time::<Fixed>::from_hz`.
+pub fn tick_duration(hz: f32) -> Duration {
+    Duration::from_secs_f64(1.0 / hz as f64)
+}
+
+fn fixed_hz(time: &Time<Fixed>) -> f32 {
+    (1.0 / time.timestep().as_secs_f64()) as f32
+}
+
+fn configure_simulation(world: &mut World, mode: SimulationMode) {
+    if mode == SimulationMode::Deterministic {
+        world.insert_resource(SubstepCount(DETERMINISTIC_SUBSTEPS));
+        // Impulses of the previous step differ between the server and a rollback
+        // starting from a restored state.
+        world.resource_mut::<SolverConfig>().warm_start_coefficient = 0.0;
+    }
+}
+
+fn system_configure_simulation(world: &mut World) {
+    let mode = *world.resource::<SimulationMode>();
+    configure_simulation(world, mode);
+}
+
+fn uses_rollback(reconciliation: Res<Reconciliation>) -> bool {
+    *reconciliation == Reconciliation::Rollback
+}
+
+fn uses_algebraic(reconciliation: Res<Reconciliation>) -> bool {
+    *reconciliation == Reconciliation::Algebraic
+}
+
+/// Time the client spent in [`ReconcileSet`].
+#[derive(Resource, Default)]
+pub struct ReconciliationStats {
+    pub time: Duration,
+    pub runs: u32,
+    started: Option<Instant>,
+}
+
+/// Inputs used by [`ClientMode::Scripted`], one per tick. The last input is
+/// repeated once the script is exhausted.
+#[derive(Resource, Clone, Default)]
+pub struct InputScript {
+    pub inputs: Vec<MovementInput>,
+}
+
+/// Default tick rate, can be changed by inserting another `Time<Fixed>`.
 const SERVER_CONFIG_HZ: f32 = 30.0;
 
 #[derive(ScheduleLabel, Hash, Debug, Eq, PartialEq, Clone)]
 pub struct Simulate;
 
+/// App without networking or rendering that only runs [`Simulate`] when asked to.
+fn simulation_app(gravity: Vec2) -> App {
+    let mut app = App::new();
+
+    app.add_plugins((
+        MinimalPlugins,
+        TransformPlugin,
+        AssetPlugin::default(),
+        RepliconSharedPlugin,
+        PhysicsPlugins::new(Simulate),
+        MovementPlugin,
+        PlatformPlugin,
+        RemoteBodyPlugin,
+    ))
+    .init_asset::<Mesh>()
+    .insert_resource(Gravity(gravity));
+
+    app.finish();
+    app.cleanup();
+    app.update();
+
+    app
+}
+
 impl Plugin for PocPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((AsyncPlugin::default_settings(), RepliconSharedPlugin));
+        app.add_plugins((AsyncPlugin::default_settings(), RepliconSharedPlugin))
+            .insert_resource(self.network.clone())
+            .init_resource::<Reconciliation>()
+            .init_resource::<SimulationMode>()
+            .add_systems(Startup, system_configure_simulation);
 
         match self.typ {
-            PocType::Client(automatic) => {
+            PocType::Client(mode) => {
                 app.add_plugins((
                     PhysicsPlugins::new(Simulate),
                     ClientPlugin,
                     ClientEventPlugin,
                     InterpolationPlugin,
-                    RepliconQuinnetClientPlugin,
+                    ConditionerPlugin::Client(self.network.conditioner.clone()),
                 ))
                     .add_observer(observe_client_added_owned)
                     .add_observer(observer_client_init_player)
                     .add_observer(observer_client_init_terrain)
+                    .add_observer(observer_client_init_platform)
+                    .add_observer(observer_client_init_projectile)
                     // .add_observer(observer_client_sync_time)
                     .add_observer(observer_client_new_config)
                     .add_systems(
                         FixedPreUpdate,
-                        (system_predict).run_if(predicted_tick_changed).in_set(PredictSystemSet),
+                        (system_predict)
+                            .run_if(predicted_tick_changed)
+                            .run_if(uses_rollback)
+                            .in_set(PredictSystemSet)
+                            .in_set(ReconcileSet),
                     )
                     .add_systems(
-                        Update,
+                        FixedPreUpdate,
                         (
-                            system_client_connection_failed,
-                            system_client_connection_handler,
+                            system_reconcile_start.before(ReconcileSet),
+                            system_reconcile_end.after(ReconcileSet),
                         ),
                     )
-                    .add_systems(Startup, system_client_init)
+                    .add_systems(
+                        Update,
+                        system_client_connection_handler.run_if(client_just_connected),
+                    )
+                    .add_systems(Startup, (system_client_init, system_init_prediction_world))
+                    .add_systems(
+                        FixedUpdate,
+                        (system_client_simulation_tick, system_update_remote_lag)
+                            .chain()
+                            .after(system_capture_input)
+                            .before(system_simulate),
+                    )
+                    .add_systems(
+                        PreUpdate,
+                        system_trace_acks
+                            .after(ClientSet::Receive)
+                            .run_if(resource_exists::<Tracer>),
+                    )
+                    .add_systems(
+                        PreUpdate,
+                        system_client_reset_interpolation.after(ClientSet::Receive),
+                    )
+                    .add_systems(
+                        FixedUpdate,
+                        (
+                            system_trace_correction.before(system_simulate),
+                            system_trace_tick.after(system_simulate),
+                        )
+                            .run_if(resource_exists::<Tracer>),
+                    )
                     .insert_resource(Gravity(Vec2::new(0.0, -1000.0)))
                     .init_resource::<ClientContext>()
-                    .init_resource::<InputMemory>();
-
-                app.add_systems(
-                    FixedPostUpdate,
-                    |query: Query<(&Predicted, &Transform, &LinearVelocity)>| {
-                        if let Ok((_, t, lv)) = query.single() {
-                            println!("after fu = y: {}, yt: {}", t.translation.y, lv.0.y);
-                        }
-                    },
-                );
+                    .init_resource::<InputMemory>()
+                    .init_resource::<RemoteBodies>()
+                    .init_resource::<ReconciliationStats>();
+
+                if let Transport::Quinnet = self.transport {
+                    app.add_plugins(RepliconQuinnetClientPlugin)
+                        .add_systems(Startup, system_client_open_connection)
+                        .add_systems(Update, system_client_connection_failed);
+                }
 
-                if automatic {
-                    app.add_systems(
-                        FixedUpdate,
-                        (
-                            system_client_input_automatic,
-                            system_capture_input,
-                            system_simulate,
+                match mode {
+                    ClientMode::Manual => {
+                        app.add_systems(
+                            FixedUpdate,
+                            (
+                                system_client_input,
+                                system_capture_input.run_if(uses_rollback),
+                                system_simulate,
+                            )
+                                .chain(),
                         )
-                            .chain(),
-                    );
-                } else {
-                    app.add_systems(
-                        FixedUpdate,
-                        (system_client_input, system_capture_input, system_simulate).chain(),
-                    );
+                        .add_systems(
+                            FixedUpdate,
+                            system_client_fire
+                                .after(system_client_simulation_tick)
+                                .before(system_simulate),
+                        );
+                    }
+                    ClientMode::Automatic => {
+                        app.add_systems(
+                            FixedUpdate,
+                            (
+                                system_client_input_automatic,
+                                system_capture_input.run_if(uses_rollback),
+                                system_simulate,
+                            )
+                                .chain(),
+                        );
+                    }
+                    ClientMode::Bot => {
+                        app.add_systems(
+                            FixedUpdate,
+                            (
+                                system_client_input_bot,
+                                system_capture_input.run_if(uses_rollback),
+                                system_simulate,
+                            )
+                                .chain(),
+                        );
+                    }
+                    ClientMode::Scripted => {
+                        app.init_resource::<InputScript>().add_systems(
+                            FixedUpdate,
+                            (
+                                system_client_input_scripted,
+                                system_capture_input.run_if(uses_rollback),
+                                system_simulate,
+                            )
+                                .chain(),
+                        );
+                    }
                 }
             }
             PocType::Server => {
                 app.add_plugins((
                     PhysicsPlugins::new(Simulate),
-                    RepliconQuinnetServerPlugin,
                     ServerPlugin {
                         tick_policy: TickPolicy::Manual,
                         replicate_after_connect: false,
@@ -156,6 +403,8 @@ impl Plugin for PocPlugin {
                         ..default()
                     },
                     ServerEventPlugin,
+                    ConditionerPlugin::Server(self.network.conditioner.clone()),
+                    TuningPlugin,
                 ))
                     //.add_observer(observer_client_init_player)
                     .add_observer(observer_client_init_terrain)
@@ -168,24 +417,69 @@ impl Plugin for PocPlugin {
                             system_server_login,
                             system_server_movement,
                             system_server_tick,
+                            system_server_simulation_tick,
                             system_simulate,
                             system_progress_input,
-                            system_ack_movment::<LinearVelocity>,
-                            system_ack_movment::<AngularVelocity>,
-                            system_ack_movment::<Transform>,
-                            system_ack_movment::<Grounded>,
                         )
                             .chain(),
                     )
+                    .configure_sets(
+                        FixedUpdate,
+                        ServerOverrideSet
+                            .after(system_simulate)
+                            .before(system_progress_input),
+                    )
+                    .add_systems(
+                        FixedUpdate,
+                        system_server_respawn.in_set(ServerOverrideSet),
+                    )
+                    .add_systems(
+                        FixedUpdate,
+                        system_record_collider_history
+                            .after(ServerOverrideSet)
+                            .before(system_progress_input),
+                    )
+                    .add_systems(
+                        PostStartup,
+                        system_record_header.run_if(resource_exists::<Recorder>),
+                    )
+                    .add_systems(
+                        FixedUpdate,
+                        (
+                            system_record_tick
+                                .after(system_server_tick)
+                                .before(system_simulate),
+                            system_record_snapshot
+                                .after(ServerOverrideSet)
+                                .before(system_progress_input),
+                        )
+                            .run_if(resource_exists::<Recorder>),
+                    )
                     .sync_related_entities::<Owned>()
                     .insert_resource(Gravity(Vec2::new(0.0, -1000.0)))
+                    .init_resource::<ServerMovementConfig>()
+                    .init_resource::<Level>()
+                    .init_resource::<SpawnPoints>()
+                    .init_resource::<LagCompensationConfig>()
                     .init_resource::<ClientContext>();
+
+                if let Transport::Quinnet = self.transport {
+                    app.add_plugins(RepliconQuinnetServerPlugin)
+                        .add_systems(Startup, system_server_start_endpoint);
+                }
             }
         }
 
-        app.add_plugins(MovementPlugin)
+        app.add_plugins((
+            MovementPlugin,
+            PlatformPlugin,
+            RemoteBodyPlugin,
+            SpawnPlugin,
+            ProjectilePlugin,
+        ))
             .replicate::<Collider>()
             .replicate::<IndividualServerConfig>()
+            .replicate::<MovementConfig>()
             .replicate::<Owned>()
             .replicate::<Player>()
             .replicate::<Terrain>()
@@ -193,6 +487,11 @@ impl Plugin for PocPlugin {
             .replicate::<AngularVelocity>()
             .replicate::<Transform>()
             .replicate::<Grounded>()
+            .replicate::<JumpState>()
+            .replicate::<WallContact>()
+            .replicate::<MovingPlatform>()
+            .replicate::<InputTickOffset>()
+            .replicate::<Discontinuity>()
             .add_event::<Login>()
             .insert_resource(Time::<Fixed>::from_hz(SERVER_CONFIG_HZ as f64))
             .add_client_event::<Login>(Channel::Ordered);
@@ -200,39 +499,189 @@ impl Plugin for PocPlugin {
         app.register_marker_with::<Predicted>(MarkerConfig {
             need_history: false,
             ..Default::default()
-        }).replicate_predicted::<LinearVelocity>()
-            .replicate_predicted::<AngularVelocity>()
-            .replicate_predicted::<Transform>()
-            .replicate_predicted::<Grounded>();
+        });
+
+        macro_rules! replicate_algebraic {
+            ($($name:ident: $component:ty),*) => {
+                $(
+                    #[cfg(debug_assertions)]
+                    if let Err(err) =
+                        algebraic::check_random_group_laws::<$component>(&mut rand::rng(), 16)
+                    {
+                        panic!(
+                            "Registered {} for algebraic reconciliation: {}",
+                            std::any::type_name::<$component>(),
+                            err
+                        );
+                    }
+
+                    app.replicate_algebraic::<$component>();
+                )*
+            };
+        }
+
+        algebraic_components!(replicate_algebraic);
+
+        app.replicate_rollback_only::<JumpState>()
+            .replicate_rollback_only::<WallContact>();
+
+        // Clients move platforms themselves at their predicted tick, the server
+        // transform would be in the past.
+        app.register_marker_with::<MovingPlatform>(MarkerConfig {
+            need_history: false,
+            ..Default::default()
+        })
+        .set_marker_fns::<MovingPlatform, Transform>(
+            |ctx, rules, entity, data| {
+                let component: Transform = rules.deserialize(ctx, data)?;
+
+                if entity.get::<Transform>().is_none() {
+                    ctx.commands.entity(entity.id()).insert(component);
+                }
+
+                Ok(())
+            },
+            |ctx, entity| {
+                ctx.commands.entity(entity.id()).remove::<Transform>();
+            },
+        );
+
+        // Kinematic remote bodies are moved from their received poses.
+        app.register_marker_with::<RemotePoses>(MarkerConfig {
+            need_history: false,
+            ..Default::default()
+        })
+        .set_marker_fns::<RemotePoses, Transform>(
+            |ctx, rules, entity, data| {
+                let component: Transform = rules.deserialize(ctx, data)?;
+
+                if let Some(mut poses) = entity.get_mut::<RemotePoses>() {
+                    poses.push_position(ctx.message_tick.get(), component.translation.truncate());
+                }
+
+                if entity.get::<Transform>().is_none() {
+                    ctx.commands.entity(entity.id()).insert(component);
+                }
+
+                Ok(())
+            },
+            |ctx, entity| {
+                ctx.commands.entity(entity.id()).remove::<Transform>();
+            },
+        )
+        .set_marker_fns::<RemotePoses, LinearVelocity>(
+            |ctx, rules, entity, data| {
+                let component: LinearVelocity = rules.deserialize(ctx, data)?;
+
+                if let Some(mut poses) = entity.get_mut::<RemotePoses>() {
+                    poses.push_velocity(ctx.message_tick.get(), component.0);
+                }
+
+                if entity.get::<LinearVelocity>().is_none() {
+                    ctx.commands.entity(entity.id()).insert(component);
+                }
+
+                Ok(())
+            },
+            |ctx, entity| {
+                ctx.commands.entity(entity.id()).remove::<LinearVelocity>();
+            },
+        );
     }
 }
 
 #[derive(SystemSet, Clone, Copy, Debug, Hash, PartialEq, Eq)]
 struct PredictSystemSet;
 
+/// Server systems that change the simulated state after [`Simulate`], e.g. to
+/// teleport players. Runs before the state of the tick is acknowledged.
+#[derive(SystemSet, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct ServerOverrideSet;
+
+/// All systems correcting the prediction, for both reconciliation strategies.
+#[derive(SystemSet, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+struct ReconcileSet;
+
+fn system_reconcile_start(mut stats: ResMut<ReconciliationStats>) {
+    stats.started = Some(Instant::now());
+}
+
+fn system_reconcile_end(mut stats: ResMut<ReconciliationStats>) {
+    if let Some(started) = stats.started.take() {
+        stats.time += started.elapsed();
+        stats.runs += 1;
+    }
+}
+
 trait AppExt {
     fn replicate_predicted<C: Clone + Component + Serialize + DeserializeOwned>(
         &mut self,
     ) -> &mut Self;
+
+    /// Like [`AppExt::replicate_predicted`], but can also be corrected with
+    /// [`Reconciliation::Algebraic`].
+    fn replicate_algebraic<C: Algebraic>(&mut self) -> &mut Self;
+
+    /// Like [`AppExt::replicate_predicted`], for components that form no group.
+    /// They are only corrected by [`Reconciliation::Rollback`], with
+    /// [`Reconciliation::Algebraic`] the server values are discarded.
+    fn replicate_rollback_only<C: Clone + Component + Serialize + DeserializeOwned>(
+        &mut self,
+    ) -> &mut Self;
 }
 
 impl AppExt for App {
     fn replicate_predicted<C: Clone + Component + Serialize + DeserializeOwned>(
         &mut self,
     ) -> &mut Self {
-        self.add_systems(FixedUpdate, system_ack_movment::<C>.run_if(server_running))
+        self.replicate::<AckComponent<C>>()
+            .add_observer(observer_server_insert_ack::<C>)
+            .add_systems(
+                FixedUpdate,
+                // Acknowledges the state after simulating the current input tick.
+                system_ack_movment::<C>
+                    .after(ServerOverrideSet)
+                    .before(system_progress_input)
+                    .run_if(server_running),
+            )
             .add_systems(
                 FixedPreUpdate,
                 (
+                    system_predict_discontinuity::<C>,
                     system_find_new_min_ack::<C>,
-                    system_predict_prune_components::<C>.run_if(predicted_tick_changed),
-                ).before(PredictSystemSet).run_if(client_connected),
+                    (
+                        system_predict_prune_components::<C>,
+                        system_predict_restore::<C>,
+                    )
+                        .chain()
+                        .run_if(predicted_tick_changed),
+                )
+                    .chain()
+                    .before(PredictSystemSet)
+                    .in_set(ReconcileSet)
+                    .run_if(client_connected)
+                    .run_if(uses_rollback),
+            )
+            .set_marker_fns::<Predicted, C>(
+                |ctx, rules, entity, data| {
+                    // Predicted values are only corrected through `AckComponent`.
+                    let component = rules.deserialize(ctx, data)?;
+
+                    if entity.get::<C>().is_none() {
+                        ctx.commands.entity(entity.id()).insert(component);
+                    }
+
+                    Ok(())
+                },
+                |ctx, entity| {
+                    ctx.commands.entity(entity.id()).remove::<C>();
+                },
             )
             .set_marker_fns::<Predicted, AckComponent<C>>(
                 |ctx, rules, entity, data| {
                     let component = rules.deserialize(ctx, data)?;
 
-                    if let None = entity.get::<C>() {
+                    if entity.get::<C>().is_none() {
                         ctx.commands
                             .entity(entity.id())
                             .insert(component.value.clone());
@@ -269,6 +718,37 @@ impl AppExt for App {
 
         self
     }
+
+    fn replicate_algebraic<C: Algebraic>(&mut self) -> &mut Self {
+        self.replicate_predicted::<C>()
+            .add_systems(
+                FixedPreUpdate,
+                (system_algebraic_snapshot::<C>, system_algebraic_correct::<C>)
+                    .chain()
+                    .in_set(ReconcileSet)
+                    .run_if(client_connected)
+                    .run_if(uses_algebraic),
+            )
+            .add_systems(
+                FixedUpdate,
+                system_record_prediction::<C>
+                    .after(system_simulate)
+                    .run_if(client_connected)
+                    .run_if(uses_algebraic),
+            )
+    }
+
+    fn replicate_rollback_only<C: Clone + Component + Serialize + DeserializeOwned>(
+        &mut self,
+    ) -> &mut Self {
+        self.replicate_predicted::<C>().add_systems(
+            FixedPreUpdate,
+            system_discard_acks::<C>
+                .in_set(ReconcileSet)
+                .run_if(client_connected)
+                .run_if(uses_algebraic),
+        )
+    }
 }
 
 #[derive(Resource, Default)]
@@ -283,8 +763,67 @@ fn system_server_tick(mut res_tick: ResMut<ServerTick>) {
     res_tick.increment();
 }
 
+fn system_server_simulation_tick(
+    server_tick: Res<ServerTick>,
+    mut simulation_tick: ResMut<SimulationTick>,
+    query: Query<(&Movement, &mut InputTickOffset)>,
+) {
+    simulation_tick.0 = server_tick.get();
+
+    for (movement, mut offset) in query {
+        if let Some(input) = &movement.input {
+            offset.set_if_neq(InputTickOffset(
+                server_tick.get().wrapping_sub(input.tick.get()),
+            ));
+        }
+    }
+}
+
+/// Predicts the server tick at which the current input will be simulated.
+fn system_client_simulation_tick(
+    client_context: Res<ClientContext>,
+    mut simulation_tick: ResMut<SimulationTick>,
+    query: Query<&InputTickOffset>,
+) {
+    let offset = client_context
+        .player_entity
+        .and_then(|entity| query.get(entity).ok())
+        .copied()
+        .unwrap_or_default();
+
+    simulation_tick.0 = client_context.tick.get().wrapping_add(offset.0);
+}
+
 fn system_simulate(world: &mut World) {
+    match *world.resource::<SimulationMode>() {
+        SimulationMode::Standard => world.run_schedule(Simulate),
+        SimulationMode::Deterministic => {
+            let dt = tick_duration(simulation_hz(world));
+            simulate_step(world, dt);
+        }
+    }
+}
+
+/// Tick rate announced by the server in [`IndividualServerConfig`]. The server
+/// and clients without a config use `Time<Fixed>`, which the config is based on.
+fn simulation_hz(world: &World) -> f32 {
+    world
+        .resource::<ClientContext>()
+        .individual_config
+        .and_then(|entity| world.get::<IndividualServerConfig>(entity))
+        .map(|config| config.hz)
+        .unwrap_or_else(|| fixed_hz(world.resource::<Time<Fixed>>()))
+}
+
+/// Runs [`Simulate`] once with a step of exactly `dt`, independent of the
+/// current `Time`, which is restored afterwards.
+fn simulate_step(world: &mut World, dt: Duration) {
+    let mut time = Time::new_with(());
+    time.advance_by(dt);
+
+    let current_time = mem::replace(world.resource_mut::<Time>().deref_mut(), time);
     world.run_schedule(Simulate);
+    *world.resource_mut::<Time>() = current_time;
 }
 
 fn system_progress_input(movement_query: Query<&mut Movement>) {
@@ -297,11 +836,7 @@ fn system_progress_input(movement_query: Query<&mut Movement>) {
     }
 }
 
-fn system_server_init(
-    mut commands: Commands,
-    mut server: ResMut<QuinnetServer>,
-    channels: Res<RepliconChannels>,
-) {
+fn system_server_init(mut commands: Commands, level: Res<Level>) {
     commands.spawn((
         Camera2d,
         Projection::Orthographic(OrthographicProjection {
@@ -310,26 +845,31 @@ fn system_server_init(
         }),
     ));
 
-    commands.spawn((
-        Terrain {
-            width: 500.0,
-            height: 20.0,
-        },
-        Transform::from_xyz(100.0, -100.0, 0.0),
-        Replicated,
-        RigidBody::Kinematic,
-        Collider::rectangle(500.0, 20.0),
-    ));
+    for piece in &level.terrain {
+        commands.spawn((piece.bundle(), Replicated));
+    }
+
+    for piece in &level.platforms {
+        commands.spawn((piece.bundle(), Replicated));
+    }
+}
 
+fn system_server_start_endpoint(
+    mut server: ResMut<QuinnetServer>,
+    channels: Res<RepliconChannels>,
+    network: Res<NetworkConfig>,
+) {
     server
         .start_endpoint(
-            ServerEndpointConfiguration::from_ip(Ipv4Addr::LOCALHOST, PORT),
+            ServerEndpointConfiguration::from_ip(network.bind.ip(), network.bind.port()),
             CertificateRetrievalMode::GenerateSelfSigned {
-                server_hostname: Ipv6Addr::LOCALHOST.to_string(),
+                server_hostname: network.bind.ip().to_string(),
             },
             channels.client_configs(),
         )
         .unwrap();
+
+    println!("Listening on {}", network.bind);
 }
 
 fn observer_server_client_connected(trigger: Trigger<OnAdd, NetworkId>, mut commands: Commands) {
@@ -357,11 +897,12 @@ fn observer_client_spawn(
     mut query_client_visibility: Query<&mut ClientVisibility>,
     query_players: Query<Entity, With<Player>>,
     query_terrain: Query<Entity, With<Terrain>>,
+    query_spawns: Query<Entity, With<PredictedSpawn>>,
     query_info: Query<&ClientInfo>,
 ) {
     let mut client_visibility = query_client_visibility.get_mut(trigger.target()).unwrap();
 
-    for entity in query_players.iter().chain(query_terrain) {
+    for entity in query_players.iter().chain(query_terrain).chain(query_spawns) {
         client_visibility.set_visibility(entity, true);
     }
 
@@ -378,9 +919,15 @@ fn system_server_login(
     mut reader_login: EventReader<FromClient<Login>>,
     mut commands: Commands,
     mut query_client_visibility: Query<&mut ClientVisibility>,
+    time: Res<Time<Fixed>>,
+    movement_config: Res<ServerMovementConfig>,
+    lag_compensation: Res<LagCompensationConfig>,
+    level: Res<Level>,
+    mut spawn_points: ResMut<SpawnPoints>,
 ) {
     for login in reader_login.read() {
         let player_id = login.player_id;
+        let spawn = spawn_points.next(&level);
 
         println!("Got login by {}", player_id);
 
@@ -390,20 +937,10 @@ fn system_server_login(
             .spawn((
                 MovementController::new(
                     collider,
-                    MovementConfig {
-                        acceleration: 2250.0,
-                        damping: 0.92,
-                        jump_impulse: 300.0,
-                        max_slope_angle: Some(30.0f32.to_radians()),
-                        max_velocity: 250.0,
-                    },
+                    movement_config.0.clone(),
                 ),
                 Player { player_id },
-                Transform::from_translation(Vec3 {
-                    x: 0.0,
-                    y: 100.0,
-                    z: 0.0,
-                }),
+                Transform::from_translation(spawn.extend(0.0)),
                 Replicated,
                 Owned {
                     owner: login.client_entity,
@@ -412,6 +949,7 @@ fn system_server_login(
                 InputAck {
                     ack_tick: RepliconTick::new(0),
                 },
+                InputTickOffset::default(),
             ))
             .id();
 
@@ -424,7 +962,8 @@ fn system_server_login(
                 Replicated,
                 IndividualServerConfig {
                     player_id: player_id,
-                    hz: SERVER_CONFIG_HZ,
+                    hz: fixed_hz(&time),
+                    interpolation_ticks: lag_compensation.interpolation_ticks,
                     owns: vec![],
                 },
             ))
@@ -441,34 +980,101 @@ fn system_server_login(
     }
 }
 
-fn system_server_movement(
+/// Teleports players that fell below the kill plane to the next spawn point.
+fn system_server_respawn(
     mut commands: Commands,
+    level: Res<Level>,
+    mut spawn_points: ResMut<SpawnPoints>,
+    server_tick: Res<ServerTick>,
+    mut recorder: Option<ResMut<Recorder>>,
+    query: Query<(
+        Entity,
+        &Player,
+        &mut Transform,
+        &mut LinearVelocity,
+        &mut AngularVelocity,
+        &mut JumpState,
+        &mut WallContact,
+    )>,
+) {
+    for (
+        entity,
+        player,
+        mut transform,
+        mut linear_velocity,
+        mut angular_velocity,
+        mut jump,
+        mut wall_contact,
+    ) in query
+    {
+        if transform.translation.y >= level.kill_plane {
+            continue;
+        }
+
+        let spawn = spawn_points.next(&level);
+
+        println!("Respawning {} at {}", player.player_id, spawn);
+
+        transform.translation = spawn.extend(0.0);
+        linear_velocity.0 = Vec2::ZERO;
+        angular_velocity.0 = 0.0;
+        *jump = JumpState::default();
+        *wall_contact = WallContact::None;
+
+        commands.entity(entity).mark_discontinuity();
+
+        if let Some(recorder) = &mut recorder {
+            recorder.record_respawn(server_tick.get(), player.player_id, spawn);
+        }
+    }
+}
+
+fn system_server_movement(
     mut read_movement: EventReader<FromClient<MovementInput>>,
     query_client_info: Query<&ClientInfo>,
-    mut query_player: Query<&mut Movement>,
+    mut query_player: Query<(&mut Movement, &mut InputAck)>,
 ) {
     for movement in read_movement.read() {
         let client_entity = movement.client_entity;
 
-        let Ok(client_info) = query_client_info.get(client_entity).cloned() else {
+        let Ok(client_info) = query_client_info.get(client_entity) else {
             println!("Client tried to move but was not initialized");
             continue;
         };
 
-        let movement = movement.event.clone();
+        let Ok((mut player, mut input_ack)) = query_player.get_mut(client_info.player) else {
+            continue;
+        };
 
-        commands.spawn_task(async move || {
-            AsyncWorld.sleep(0.25).await;
+        // Inputs are sent unreliably and may arrive out of order.
+        if movement.event.tick <= input_ack.ack_tick {
+            continue;
+        }
 
-            AsyncWorld.query::<&mut Movement>().get_mut(|mut x| {
-                let mut player = x.get_mut(client_info.player).unwrap();
-                player.input = Some(movement.clone());
-                player.uses = 0;
-            })?;
+        input_ack.ack_tick = movement.event.tick;
+        player.input = Some(movement.event.clone());
+        player.uses = 0;
+    }
+}
 
-            Ok(())
-        });
+fn observer_server_insert_ack<C: Component + Clone>(
+    trigger: Trigger<OnAdd, Movement>,
+    mut commands: Commands,
+    server: Option<Res<RepliconServer>>,
+    query: Query<&C>,
+) {
+    if !server.is_some_and(|server| server.is_running()) {
+        return;
     }
+
+    let Ok(component) = query.get(trigger.target()) else {
+        return;
+    };
+
+    commands.entity(trigger.target()).insert(AckComponent {
+        ack_tick: RepliconTick::new(0),
+        value: component.clone(),
+    });
 }
 
 fn system_ack_movment<C: Component + Clone>(
@@ -484,11 +1090,7 @@ fn system_ack_movment<C: Component + Clone>(
     }
 }
 
-fn system_client_init(
-    mut commands: Commands,
-    mut client: ResMut<QuinnetClient>,
-    channels: Res<RepliconChannels>,
-) {
+fn system_client_init(mut commands: Commands) {
     commands.spawn((
         Camera2d,
         Projection::Orthographic(OrthographicProjection {
@@ -496,37 +1098,41 @@ fn system_client_init(
             ..OrthographicProjection::default_2d()
         }),
     ));
+}
 
+fn system_client_open_connection(
+    mut client: ResMut<QuinnetClient>,
+    channels: Res<RepliconChannels>,
+    network: Res<NetworkConfig>,
+) {
     client
         .open_connection(
             ClientEndpointConfiguration::from_ips(
-                Ipv4Addr::LOCALHOST,
-                PORT,
-                Ipv6Addr::UNSPECIFIED,
+                network.connect.ip(),
+                network.connect.port(),
+                network.client_local_ip(),
                 0,
             ),
             CertificateVerificationMode::SkipVerification,
             channels.client_configs(),
         )
         .unwrap();
+
+    println!("Connecting to {}", network.connect);
 }
 
 fn system_client_connection_handler(
-    mut commands: Commands,
-    mut reader_connected: EventReader<ConnectionEvent>,
     mut writer_login: EventWriter<Login>,
     mut res_client_context: ResMut<ClientContext>,
 ) {
-    for _ in reader_connected.read() {
-        let player_id = rand::random::<u64>();
+    let player_id = rand::random::<u64>();
 
-        println!("spawning with {}", player_id);
+    println!("spawning with {}", player_id);
 
-        res_client_context.player_id = Some(player_id);
-        writer_login.write(Login { player_id });
+    res_client_context.player_id = Some(player_id);
+    writer_login.write(Login { player_id });
 
-        println!("Logging in {}", player_id)
-    }
+    println!("Logging in {}", player_id)
 }
 
 fn system_client_connection_failed(
@@ -538,16 +1144,17 @@ fn system_client_connection_failed(
 }
 
 fn observer_client_init_player(
-    trigger: Trigger<OnAdd, (Player, Collider)>,
+    trigger: Trigger<OnAdd, (Player, Collider, MovementConfig)>,
     mut commands: Commands,
-    query_player: Query<(&Player, &Collider)>,
+    query_player: Query<(&Player, &Collider, &MovementConfig)>,
     mut res_client_context: ResMut<ClientContext>,
     mut res_meshes: ResMut<Assets<Mesh>>,
     mut res_materials: ResMut<Assets<ColorMaterial>>,
+    remote_bodies: Res<RemoteBodies>,
 ) {
     let mut player_entity = commands.entity(trigger.target());
 
-    let Ok((player, collider)) = query_player.get(trigger.target()) else {
+    let Ok((player, collider, movement_config)) = query_player.get(trigger.target()) else {
         return;
     };
 
@@ -561,20 +1168,19 @@ fn observer_client_init_player(
         .map(|x| x == player.player_id)
         .unwrap_or(false)
     {
+        // The server replicates its tuning, so prediction uses the same values.
         player_entity.insert(MovementController::new(
             collider.clone(),
-            MovementConfig {
-                acceleration: 2250.0,
-                damping: 0.92,
-                jump_impulse: 300.0,
-                max_slope_angle: Some(30.0f32.to_radians()),
-                max_velocity: 250.0,
-            },
+            movement_config.clone(),
         ));
 
         res_client_context.player_entity = Some(trigger.target());
-    } else {
+    } else if let RemoteBodies::Static = *remote_bodies {
         player_entity.insert((RigidBody::Static, Interpolation::default()));
+    } else {
+        // The server pushes players authoritatively, the predicted player only
+        // collides with the poses of the others.
+        player_entity.insert((RigidBody::Kinematic, RemotePoses::default()));
     }
 }
 
@@ -594,18 +1200,58 @@ fn observer_client_sync_time(
 
 fn observer_client_init_terrain(
     trigger: Trigger<OnAdd, Terrain>,
-    query_terrain: Query<&Terrain>,
+    query_terrain: Query<(&Terrain, Has<MovingPlatform>)>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    let terrain = query_terrain.get(trigger.target()).unwrap();
+    let (terrain, platform) = query_terrain.get(trigger.target()).unwrap();
 
-    commands.entity(trigger.target()).insert((
-        Mesh2d(meshes.add(Rectangle::new(terrain.width, terrain.height))),
+    let mut entity = commands.entity(trigger.target());
+
+    entity.insert((
+        Mesh2d(meshes.add(terrain.shape.mesh())),
         MeshMaterial2d(materials.add(Color::srgb(0.75, 0.75, 0.75))),
-        RigidBody::Static,
     ));
+
+    if !platform {
+        entity.insert(RigidBody::Static);
+    }
+}
+
+/// Restarts the interpolation of remote players after a [`Discontinuity`], so
+/// they jump to the new pose instead of sliding there.
+fn system_client_reset_interpolation(
+    mut commands: Commands,
+    query: Query<(Entity, Has<Interpolation>, Option<&mut RemotePoses>), Changed<Discontinuity>>,
+) {
+    for (entity, interpolation, poses) in query {
+        if interpolation {
+            commands.entity(entity).insert(Interpolation::default());
+        }
+
+        if let Some(mut poses) = poses {
+            poses.reset();
+        }
+    }
+}
+
+fn observer_client_init_projectile(
+    trigger: Trigger<OnAdd, Projectile>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands.entity(trigger.target()).insert((
+        Mesh2d(meshes.add(Circle::new(4.0))),
+        MeshMaterial2d(materials.add(Color::srgb(1.0, 0.8, 0.0))),
+    ));
+}
+
+fn observer_client_init_platform(trigger: Trigger<OnAdd, MovingPlatform>, mut commands: Commands) {
+    commands
+        .entity(trigger.target())
+        .insert(RigidBody::Kinematic);
 }
 
 fn system_client_input(
@@ -628,6 +1274,103 @@ fn system_client_input(
         input.jump = true;
     }
 
+    submit_input(
+        input,
+        &mut writer_movement,
+        &mut query_movment,
+        &mut res_client_context,
+    );
+}
+
+/// Fires a projectile in the direction the player moves, predicted until the
+/// server confirms it.
+fn system_client_fire(
+    mut commands: Commands,
+    mut local_spawns: ResMut<LocalSpawns>,
+    mut writer: EventWriter<SpawnRequest<Projectile>>,
+    tick: Res<SimulationTick>,
+    res_keyboard: Res<ButtonInput<KeyCode>>,
+    query: Query<(&Transform, &LinearVelocity), With<Predicted>>,
+) {
+    if !res_keyboard.just_pressed(KeyCode::Space) {
+        return;
+    }
+
+    let Ok((transform, linear_velocity)) = query.single() else {
+        return;
+    };
+
+    let direction = if linear_velocity.x < 0.0 { -1.0 } else { 1.0 };
+
+    local_spawns.spawn(
+        &mut commands,
+        &mut writer,
+        Projectile {
+            origin: transform.translation.truncate() + Vec2::new(direction * 20.0, 0.0),
+            velocity: Vec2::new(direction * PROJECTILE_SPEED, 0.0),
+            tick: tick.0,
+        },
+    );
+}
+
+#[derive(Default)]
+struct BotState {
+    direction: f32,
+    jump: bool,
+    remaining_ticks: u32,
+}
+
+fn system_client_input_bot(
+    mut bot: Local<BotState>,
+    mut writer_movement: EventWriter<MovementInput>,
+    mut query_movment: Query<(&Transform, &mut Movement)>,
+    mut res_client_context: ResMut<ClientContext>,
+) {
+    if bot.remaining_ticks == 0 {
+        bot.direction = rand::random_range(-1.0..=1.0);
+        bot.jump = rand::random_bool(0.2);
+        bot.remaining_ticks = rand::random_range(5..60);
+    }
+
+    bot.remaining_ticks -= 1;
+
+    let input = MovementInput {
+        direction: bot.direction,
+        jump: bot.jump,
+        ..default()
+    };
+
+    submit_input(
+        input,
+        &mut writer_movement,
+        &mut query_movment,
+        &mut res_client_context,
+    );
+}
+
+fn system_client_input_scripted(
+    script: Res<InputScript>,
+    mut writer_movement: EventWriter<MovementInput>,
+    mut query_movment: Query<(&Transform, &mut Movement)>,
+    mut res_client_context: ResMut<ClientContext>,
+) {
+    let index = (res_client_context.tick.get() as usize).min(script.inputs.len().saturating_sub(1));
+    let input = script.inputs.get(index).cloned().unwrap_or_default();
+
+    submit_input(
+        input,
+        &mut writer_movement,
+        &mut query_movment,
+        &mut res_client_context,
+    );
+}
+
+fn submit_input(
+    mut input: MovementInput,
+    writer_movement: &mut EventWriter<MovementInput>,
+    query_movment: &mut Query<(&Transform, &mut Movement)>,
+    res_client_context: &mut ClientContext,
+) {
     res_client_context.tick += 1;
     input.tick = res_client_context.tick;
 
@@ -639,7 +1382,7 @@ fn system_client_input(
         movement.input = Some(input.clone());
         movement.uses = 0;
 
-        println!("Input y={}, {}", transform.translation.y, input.jump);
+        trace!("Input y={}, {}", transform.translation.y, input.jump);
     }
 }
 
@@ -675,7 +1418,7 @@ fn system_find_new_min_ack<C: Component>(
             continue;
         };
 
-        println!("memorytick: {:?}, predictedtick: {:?}", memory_tick, predicted_tick);
+        trace!("memorytick: {:?}, predictedtick: {:?}", memory_tick, predicted_tick);
 
         if predicted_tick > memory_tick {
             input_memory.new_min_ack = Some(RepliconTick::new(memory_tick));
@@ -683,6 +1426,24 @@ fn system_find_new_min_ack<C: Component>(
     }
 }
 
+/// Drops acknowledged values from before a [`Discontinuity`] once its value
+/// arrived, so rollbacks start from the new state.
+fn system_predict_discontinuity<C: Component>(
+    memory_query: Query<(&Discontinuity, &mut PredictedMemory<C>), With<Predicted>>,
+) {
+    for (discontinuity, mut memory) in memory_query {
+        let Some(index) = memory
+            .values
+            .iter()
+            .position(|(_, tick)| *tick >= discontinuity.tick)
+        else {
+            continue;
+        };
+
+        memory.values.drain(0..index);
+    }
+}
+
 fn predicted_tick_changed(input_memory: Res<InputMemory>) -> bool {
     input_memory.new_min_ack.map(|x| x.get() > input_memory.current_min_ack.get()).unwrap_or(false)
 }
@@ -706,6 +1467,16 @@ fn system_predict_prune_components<C: Component>(
     }
 }
 
+fn system_predict_restore<C: Component + Clone>(
+    memory_query: Query<(&mut C, &PredictedMemory<C>)>,
+) {
+    for (mut component, memory) in memory_query {
+        if let Some((value, _)) = memory.values.front() {
+            *component = value.clone();
+        }
+    }
+}
+
 fn system_predict(world: &mut World) {
     world.resource_scope(|world, client_context: Mut<ClientContext>| {
         let Some(player_entity) = client_context.player_entity else {
@@ -723,7 +1494,7 @@ fn system_predict(world: &mut World) {
                 .unwrap();
 
             let current_tick = input_memory.new_min_ack.unwrap();
-            let dt = Duration::from_secs_f32(1.0 / config.hz);
+            let dt = tick_duration(config.hz);
 
             if let Some(remove_to) = input_memory
                 .inputs
@@ -735,34 +1506,29 @@ fn system_predict(world: &mut World) {
                 input_memory.inputs.clear();
             }
 
-            let mut movement_query = world.query::<&mut Movement>();
-            // let mut transform_query = world.query::<&mut Transform>();
-            // let mut lin_vel_query = world.query::<&LinearVelocity>();
-
-            // let transform = transform_query.get(world, player_entity).unwrap();
-            // let linvel = lin_vel_query.get(world, player_entity).unwrap();
-
-            world.schedule_scope(Simulate, |world, schedule| {
-                let current_time = world.resource::<Time>().as_generic();
-                *world.resource_mut::<Time>() = Time::new_with(());
-
-                for (_, input) in &input_memory.inputs {
-                    let mut movement = movement_query.get_mut(world, player_entity).unwrap();
-                    movement.input = Some(input.clone());
-                    movement.uses = 0;
-
-                    world.resource_mut::<Time>().advance_by(dt);
-
-                    schedule.run(world);
-                }
-
-                *world.resource_mut::<Time>() = current_time;
+            let tick_offset = world
+                .get::<InputTickOffset>(player_entity)
+                .copied()
+                .unwrap_or_default();
+
+            world.resource_scope(|world, mut prediction: Mut<PredictionWorld>| {
+                prediction.replay(
+                    world,
+                    &[player_entity],
+                    input_memory.inputs.iter().map(|(_, input)| input),
+                    dt,
+                    tick_offset.0,
+                );
             });
 
             let mut transform_query = world.query::<&mut Transform>();
             let transform = transform_query.get(world, player_entity).unwrap();
             if input_memory.inputs.len() > 0 {
-                println!("After y={}\n", transform.translation.y);
+                trace!("After y={}", transform.translation.y);
+            }
+
+            if let Some(mut tracer) = world.get_resource_mut::<Tracer>() {
+                tracer.replayed += input_memory.inputs.len() as u32;
             }
 
             input_memory.current_min_ack = current_tick;
@@ -771,6 +1537,31 @@ fn system_predict(world: &mut World) {
     })
 }
 
+/// Runs [`Simulate`] once per input with a step of `dt`, after applying the input
+/// to all `entities`. The [`SimulationTick`] of each step is the input tick plus
+/// `tick_offset`. The `Time` resource is restored afterwards.
+pub fn replay_inputs<'a>(
+    world: &mut World,
+    entities: &[Entity],
+    inputs: impl IntoIterator<Item = &'a MovementInput>,
+    dt: Duration,
+    tick_offset: u32,
+) {
+    let mut movement_query = world.query::<&mut Movement>();
+
+    for input in inputs {
+        world.insert_resource(SimulationTick(input.tick.get().wrapping_add(tick_offset)));
+
+        for &entity in entities {
+            let mut movement = movement_query.get_mut(world, entity).unwrap();
+            movement.input = Some(input.clone());
+            movement.uses = 0;
+        }
+
+        simulate_step(world, dt);
+    }
+}
+
 fn observer_client_new_config(
     trigger: Trigger<OnAdd, IndividualServerConfig>,
     query_server_config: Query<&IndividualServerConfig>,
@@ -812,6 +1603,8 @@ struct InputMemory {
 pub struct IndividualServerConfig {
     pub hz: f32,
     pub player_id: u64,
+    /// See [`LagCompensationConfig::interpolation_ticks`].
+    pub interpolation_ticks: u32,
 
     #[relationship]
     owns: Vec<Entity>,
@@ -830,6 +1623,43 @@ pub struct InputAck {
     pub ack_tick: RepliconTick,
 }
 
+/// Server tick minus the tick of the input the server simulates at that tick.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct InputTickOffset(pub u32);
+
+/// Server side change of a predicted entity's state that the client cannot
+/// predict, e.g. a teleport. The state acknowledged at `tick` replaces the
+/// prediction instead of correcting it, predictions for earlier ticks continue
+/// the motion from before the change.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Discontinuity {
+    pub tick: RepliconTick,
+}
+
+pub trait DiscontinuityExt {
+    /// Flags the current state as a [`Discontinuity`] at the input tick it is
+    /// acknowledged with. Has to run in [`ServerOverrideSet`].
+    fn mark_discontinuity(&mut self) -> &mut Self;
+}
+
+impl DiscontinuityExt for EntityCommands<'_> {
+    fn mark_discontinuity(&mut self) -> &mut Self {
+        self.queue(|mut entity: EntityWorldMut| {
+            // Without a current input the state is acknowledged with the next one.
+            let tick = match (
+                entity.get::<Movement>().and_then(|x| x.input.as_ref()),
+                entity.get::<InputAck>(),
+            ) {
+                (Some(input), _) => input.tick,
+                (None, Some(input_ack)) => RepliconTick::new(input_ack.ack_tick.get() + 1),
+                (None, None) => return,
+            };
+
+            entity.insert(Discontinuity { tick });
+        })
+    }
+}
+
 #[derive(Component)]
 pub struct Predicted;
 
@@ -863,6 +1693,5 @@ struct Player {
 
 #[derive(Component, Serialize, Deserialize)]
 struct Terrain {
-    width: f32,
-    height: f32,
+    shape: TerrainShape,
 }
@@ -0,0 +1,459 @@
+use std::collections::VecDeque;
+
+use avian2d::prelude::{AngularVelocity, LinearVelocity};
+use bevy::prelude::*;
+use bevy_replicon::shared::replicon_tick::RepliconTick;
+#[cfg(any(test, debug_assertions, feature = "bench"))]
+use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{ClientContext, Discontinuity, Predicted, PredictedMemory};
+
+/// Number of predicted values kept per component if no correction arrives.
+const MAX_HISTORY: usize = 256;
+
+/// Relative tolerance used when checking the group laws on floating point values.
+pub const LAW_TOLERANCE: f32 = 1e-4;
+
+/// A predicted component whose delta state forms an Abelian group with
+/// `S = ΔS` and `f^A = combine`. This allows correcting a prediction by
+/// combining it with the difference between server and predicted state,
+/// instead of replaying inputs.
+pub trait Algebraic: Clone + Component + Serialize + DeserializeOwned {
+    fn identity() -> Self;
+
+    /// The group operation. Has to be associative and commutative.
+    fn combine(&self, other: &Self) -> Self;
+
+    fn inverse(&self) -> Self;
+
+    /// Distance between two values, used to compare with a tolerance.
+    fn distance(&self, other: &Self) -> f32;
+
+    /// Size of a value, used to scale the tolerance of floating point errors.
+    fn magnitude(&self) -> f32;
+
+    fn difference(&self, other: &Self) -> Self {
+        self.combine(&other.inverse())
+    }
+}
+
+impl Algebraic for LinearVelocity {
+    fn identity() -> Self {
+        LinearVelocity::ZERO
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        LinearVelocity(self.0 + other.0)
+    }
+
+    fn inverse(&self) -> Self {
+        LinearVelocity(-self.0)
+    }
+
+    fn distance(&self, other: &Self) -> f32 {
+        self.0.distance(other.0)
+    }
+
+    fn magnitude(&self) -> f32 {
+        self.0.length()
+    }
+}
+
+impl Algebraic for AngularVelocity {
+    fn identity() -> Self {
+        AngularVelocity::ZERO
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        AngularVelocity(self.0 + other.0)
+    }
+
+    fn inverse(&self) -> Self {
+        AngularVelocity(-self.0)
+    }
+
+    fn distance(&self, other: &Self) -> f32 {
+        (self.0 - other.0).abs()
+    }
+
+    fn magnitude(&self) -> f32 {
+        self.0.abs()
+    }
+}
+
+/// Direct product of translation under addition, rotation around the z axis
+/// under composition and scale under multiplication. Rotations in 2D commute,
+/// so unlike transform composition this is an Abelian group.
+impl Algebraic for Transform {
+    fn identity() -> Self {
+        Transform::IDENTITY
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Transform {
+            translation: self.translation + other.translation,
+            rotation: (self.rotation * other.rotation).normalize(),
+            scale: self.scale * other.scale,
+        }
+    }
+
+    fn inverse(&self) -> Self {
+        Transform {
+            translation: -self.translation,
+            rotation: self.rotation.inverse(),
+            scale: self.scale.recip(),
+        }
+    }
+
+    fn distance(&self, other: &Self) -> f32 {
+        // Chord length between the quaternions instead of `angle_between`, whose
+        // `acos` loses precision near the identity. `q` and `-q` are the same rotation.
+        let rotation = (self.rotation - other.rotation)
+            .length()
+            .min((self.rotation + other.rotation).length());
+
+        self.translation.distance(other.translation) + rotation + self.scale.distance(other.scale)
+    }
+
+    fn magnitude(&self) -> f32 {
+        self.translation.length() + self.scale.length()
+    }
+}
+
+/// Random values of an [`Algebraic`] type for the group law checks and the
+/// benchmarks. Not available in release builds.
+#[cfg(any(test, debug_assertions, feature = "bench"))]
+pub trait RandomAlgebraic: Algebraic {
+    fn random(rng: &mut impl Rng) -> Self;
+}
+
+#[cfg(any(test, debug_assertions, feature = "bench"))]
+impl RandomAlgebraic for LinearVelocity {
+    fn random(rng: &mut impl Rng) -> Self {
+        LinearVelocity(random_vec2(rng, 1000.0))
+    }
+}
+
+#[cfg(any(test, debug_assertions, feature = "bench"))]
+impl RandomAlgebraic for AngularVelocity {
+    fn random(rng: &mut impl Rng) -> Self {
+        AngularVelocity(rng.random_range(-100.0..100.0))
+    }
+}
+
+#[cfg(any(test, debug_assertions, feature = "bench"))]
+impl RandomAlgebraic for Transform {
+    fn random(rng: &mut impl Rng) -> Self {
+        Transform {
+            translation: random_vec2(rng, 1000.0).extend(0.0),
+            rotation: Quat::from_rotation_z(
+                rng.random_range(-std::f32::consts::PI..std::f32::consts::PI),
+            ),
+            scale: Vec3::new(
+                rng.random_range(0.1..10.0),
+                rng.random_range(0.1..10.0),
+                1.0,
+            ),
+        }
+    }
+}
+
+#[cfg(any(test, debug_assertions, feature = "bench"))]
+fn random_vec2(rng: &mut impl Rng, range: f32) -> Vec2 {
+    Vec2::new(
+        rng.random_range(-range..range),
+        rng.random_range(-range..range),
+    )
+}
+
+fn approx_eq<C: Algebraic>(a: &C, b: &C, scale: f32) -> bool {
+    a.distance(b) <= LAW_TOLERANCE * (1.0 + scale)
+}
+
+/// Checks associativity, commutativity, identity and inverses for the given values.
+pub fn check_group_laws<C: Algebraic>(a: &C, b: &C, c: &C) -> Result<(), String> {
+    let name = std::any::type_name::<C>();
+    let scale = a.magnitude() + b.magnitude() + c.magnitude();
+
+    if !approx_eq(&a.combine(b).combine(c), &a.combine(&b.combine(c)), scale) {
+        return Err(format!("{} is not associative", name));
+    }
+
+    if !approx_eq(&a.combine(b), &b.combine(a), scale) {
+        return Err(format!("{} is not commutative", name));
+    }
+
+    if !approx_eq(&a.combine(&C::identity()), a, scale) {
+        return Err(format!("{} has no identity", name));
+    }
+
+    if !approx_eq(&a.combine(&a.inverse()), &C::identity(), scale) {
+        return Err(format!("{} has no inverse", name));
+    }
+
+    Ok(())
+}
+
+/// Runs [`check_group_laws`] on random values, used when registering a type.
+#[cfg(any(test, debug_assertions, feature = "bench"))]
+pub fn check_random_group_laws<C: RandomAlgebraic>(
+    rng: &mut impl Rng,
+    samples: usize,
+) -> Result<(), String> {
+    for _ in 0..samples {
+        let (a, b, c) = (C::random(rng), C::random(rng), C::random(rng));
+        check_group_laws(&a, &b, &c)?;
+    }
+
+    Ok(())
+}
+
+/// Predicted values of a component after simulating the input of each tick.
+#[derive(Component)]
+pub struct PredictedHistory<C: Component> {
+    pub values: VecDeque<(C, RepliconTick)>,
+}
+
+pub(super) fn system_record_prediction<C: Component + Clone>(
+    mut commands: Commands,
+    client_context: Res<ClientContext>,
+    mut query: Query<(Entity, &C, Option<&mut PredictedHistory<C>>), With<Predicted>>,
+) {
+    for (entity, component, history) in &mut query {
+        let Some(mut history) = history else {
+            commands.entity(entity).insert(PredictedHistory {
+                values: VecDeque::from([(component.clone(), client_context.tick)]),
+            });
+            continue;
+        };
+
+        history
+            .values
+            .push_back((component.clone(), client_context.tick));
+
+        if history.values.len() > MAX_HISTORY {
+            history.values.pop_front();
+        }
+    }
+}
+
+/// Treats the server value of a [`Discontinuity`] as a snapshot instead of a
+/// correction. The predictions made before the client knew about it continue
+/// the old motion, so their difference to the server means nothing. The
+/// history is from before the discontinuity as long as it contains its tick,
+/// afterwards it only has later ticks.
+pub(super) fn system_algebraic_snapshot<C: Algebraic>(
+    mut query: Query<
+        (
+            &Discontinuity,
+            &mut C,
+            &mut PredictedMemory<C>,
+            &mut PredictedHistory<C>,
+        ),
+        With<Predicted>,
+    >,
+) {
+    for (discontinuity, mut current, mut memory, mut history) in &mut query {
+        if history
+            .values
+            .front()
+            .is_none_or(|(_, tick)| *tick > discontinuity.tick)
+        {
+            continue;
+        }
+
+        let Some(index) = memory
+            .values
+            .iter()
+            .position(|(_, tick)| *tick >= discontinuity.tick)
+        else {
+            continue;
+        };
+
+        let (server_value, _) = memory.values.drain(..=index).last().unwrap();
+
+        *current = server_value;
+        history.values.clear();
+    }
+}
+
+/// For every server value `s_t` adds the correction `s_t - p_t` to the current
+/// state and to all predictions made after `t`.
+pub(super) fn system_algebraic_correct<C: Algebraic>(
+    mut query: Query<(&mut C, &mut PredictedMemory<C>, &mut PredictedHistory<C>), With<Predicted>>,
+) {
+    for (mut current, mut memory, mut history) in &mut query {
+        for (server_value, ack_tick) in memory.values.drain(..) {
+            while history
+                .values
+                .front()
+                .is_some_and(|(_, tick)| *tick < ack_tick)
+            {
+                history.values.pop_front();
+            }
+
+            let Some((predicted, tick)) = history.values.pop_front() else {
+                continue;
+            };
+
+            if tick != ack_tick {
+                // The prediction for this tick is unknown, wait for a later value.
+                history.values.push_front((predicted, tick));
+                continue;
+            }
+
+            let correction = server_value.difference(&predicted);
+
+            *current = current.combine(&correction);
+
+            for (value, _) in history.values.iter_mut() {
+                *value = value.combine(&correction);
+            }
+        }
+    }
+}
+
+/// Drops the server values of components without a group operation. An
+/// acknowledged value is about one round trip old, copying it into the current
+/// state would undo newer inputs, e.g. restore a used air jump. These
+/// components follow from the corrected algebraic state instead, like jump
+/// timers that reset on landing.
+pub(super) fn system_discard_acks<C: Component>(
+    mut query: Query<&mut PredictedMemory<C>, With<Predicted>>,
+) {
+    for mut memory in &mut query {
+        memory.values.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use avian2d::prelude::{AngularVelocity, LinearVelocity};
+    use bevy::prelude::{Quat, Transform, Vec2, Vec3};
+    use proptest::prelude::*;
+
+    use super::{approx_eq, check_group_laws, Algebraic};
+    use crate::poc::movement::Grounded;
+
+    /// Values of a type for the group law tests, including edge cases that
+    /// uniform sampling rarely hits.
+    trait GroupStrategy: Algebraic + std::fmt::Debug {
+        fn strategy() -> BoxedStrategy<Self>;
+    }
+
+    fn scalar(range: f32) -> impl Strategy<Value = f32> {
+        prop_oneof![
+            Just(0.0),
+            Just(range),
+            Just(-range),
+            Just(f32::EPSILON),
+            -range..range,
+        ]
+    }
+
+    fn vec2(range: f32) -> impl Strategy<Value = Vec2> {
+        (scalar(range), scalar(range)).prop_map(|(x, y)| Vec2::new(x, y))
+    }
+
+    impl GroupStrategy for LinearVelocity {
+        fn strategy() -> BoxedStrategy<Self> {
+            vec2(1e6).prop_map(LinearVelocity).boxed()
+        }
+    }
+
+    impl GroupStrategy for AngularVelocity {
+        fn strategy() -> BoxedStrategy<Self> {
+            scalar(1e6).prop_map(AngularVelocity).boxed()
+        }
+    }
+
+    impl GroupStrategy for Transform {
+        fn strategy() -> BoxedStrategy<Self> {
+            let angle = prop_oneof![Just(0.0), Just(1e-6), Just(-1e-6), Just(PI), -PI..PI];
+            // Zero has no inverse under multiplication.
+            let scale = prop_oneof![Just(1.0), 0.1f32..10.0];
+
+            (vec2(1e6), angle, scale.clone(), scale)
+                .prop_map(|(translation, angle, x, y)| Transform {
+                    translation: translation.extend(0.0),
+                    rotation: Quat::from_rotation_z(angle),
+                    scale: Vec3::new(x, y, 1.0),
+                })
+                .boxed()
+        }
+    }
+
+    impl GroupStrategy for Grounded {
+        fn strategy() -> BoxedStrategy<Self> {
+            any::<bool>().prop_map(Grounded).boxed()
+        }
+    }
+
+    macro_rules! group_law_tests {
+        ($($name:ident: $component:ty),*) => {
+            $(
+                mod $name {
+                    use super::*;
+
+                    proptest! {
+                        #[test]
+                        fn is_abelian_group(
+                            a in <$component>::strategy(),
+                            b in <$component>::strategy(),
+                            c in <$component>::strategy(),
+                        ) {
+                            check_group_laws(&a, &b, &c).map_err(TestCaseError::fail)?;
+                        }
+
+                        #[test]
+                        fn correction_reaches_server_state(
+                            server in <$component>::strategy(),
+                            predicted in <$component>::strategy(),
+                        ) {
+                            // p + (s - p) = s for the value at the acknowledged tick.
+                            let corrected = predicted.combine(&server.difference(&predicted));
+                            let scale = server.magnitude() + predicted.magnitude();
+
+                            prop_assert!(approx_eq(&corrected, &server, scale));
+                        }
+                    }
+                }
+            )*
+        };
+    }
+
+    algebraic_components!(group_law_tests);
+
+    #[test]
+    fn tolerance_rejects_non_commutative_operation() {
+        #[derive(Clone, bevy::prelude::Component, serde::Serialize, serde::Deserialize)]
+        struct Subtract(f32);
+
+        impl Algebraic for Subtract {
+            fn identity() -> Self {
+                Subtract(0.0)
+            }
+
+            fn combine(&self, other: &Self) -> Self {
+                Subtract(self.0 - other.0)
+            }
+
+            fn inverse(&self) -> Self {
+                Subtract(self.0)
+            }
+
+            fn distance(&self, other: &Self) -> f32 {
+                (self.0 - other.0).abs()
+            }
+
+            fn magnitude(&self) -> f32 {
+                self.0.abs()
+            }
+        }
+
+        assert!(check_group_laws(&Subtract(1.0), &Subtract(2.0), &Subtract(3.0)).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use super::{
+    trace::{read_trace, Trace},
+    Reconciliation,
+};
+
+const PANEL_WIDTH: f32 = 480.0;
+const PANEL_HEIGHT: f32 = 160.0;
+const MARGIN: f32 = 50.0;
+const LEGEND_LINE_HEIGHT: f32 = 16.0;
+const COLORS: [&str; 6] = [
+    "#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b",
+];
+
+pub struct TickSample {
+    pub tick: u32,
+    /// Distance the reconciliation moved the player before this tick.
+    pub correction: f32,
+    pub replayed: u32,
+    /// Round trip time in milliseconds, based on the newest acknowledged tick.
+    pub rtt: Option<f32>,
+}
+
+pub struct TraceAnalysis {
+    pub name: String,
+    pub reconciliation: Reconciliation,
+    pub samples: Vec<TickSample>,
+}
+
+impl TraceAnalysis {
+    pub fn new(name: String, trace: &Trace) -> Self {
+        let samples =
+            trace
+                .ticks
+                .iter()
+                .map(|tick| TickSample {
+                    tick: tick.tick,
+                    correction: tick.correction.position.length(),
+                    replayed: tick.replayed,
+                    rtt: tick.acks.iter().map(|ack| ack.tick).max().map(|ack_tick| {
+                        tick.tick.saturating_sub(ack_tick) as f32 * 1000.0 / trace.hz
+                    }),
+                })
+                .collect();
+
+        Self {
+            name,
+            reconciliation: trace.reconciliation,
+            samples,
+        }
+    }
+}
+
+struct Metric {
+    name: &'static str,
+    value: fn(&TickSample) -> Option<f32>,
+}
+
+const METRICS: [Metric; 3] = [
+    Metric {
+        name: "correction",
+        value: |x| Some(x.correction),
+    },
+    Metric {
+        name: "replayed inputs",
+        value: |x| Some(x.replayed as f32),
+    },
+    Metric {
+        name: "rtt (ms)",
+        value: |x| x.rtt,
+    },
+];
+
+/// Writes `analysis.csv` and `analysis.svg` for the given client traces to `out`.
+pub fn analyze(traces: &[PathBuf], out: &Path) -> Result<()> {
+    let mut analyses = Vec::new();
+
+    for path in traces {
+        let name = path
+            .file_stem()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.display().to_string());
+
+        analyses.push(TraceAnalysis::new(name, &read_trace(path)?));
+    }
+
+    fs::create_dir_all(out)?;
+    write_csv(&out.join("analysis.csv"), &analyses)?;
+    fs::write(out.join("analysis.svg"), svg(&analyses))?;
+
+    for analysis in &analyses {
+        let count = analysis.samples.len().max(1) as f32;
+        let corrections = analysis.samples.iter().map(|x| x.correction);
+
+        println!(
+            "{} ({:?}): {} ticks, mean correction {:.3}, max correction {:.3}, {} replayed inputs",
+            analysis.name,
+            analysis.reconciliation,
+            analysis.samples.len(),
+            corrections.clone().sum::<f32>() / count,
+            corrections.fold(0.0, f32::max),
+            analysis.samples.iter().map(|x| x.replayed).sum::<u32>()
+        );
+    }
+
+    println!("Wrote analysis to {}", out.display());
+
+    Ok(())
+}
+
+pub fn write_csv(path: &Path, analyses: &[TraceAnalysis]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writeln!(
+        writer,
+        "trace,reconciliation,tick,correction,replayed,rtt_ms"
+    )?;
+
+    for analysis in analyses {
+        for sample in &analysis.samples {
+            writeln!(
+                writer,
+                "{},{:?},{},{},{},{}",
+                analysis.name,
+                analysis.reconciliation,
+                sample.tick,
+                sample.correction,
+                sample.replayed,
+                sample.rtt.map(|x| x.to_string()).unwrap_or_default()
+            )?;
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// One row per metric and one column per reconciliation strategy. Rows share
+/// their scale, so the strategies can be compared directly.
+pub fn svg(analyses: &[TraceAnalysis]) -> String {
+    let strategies = [Reconciliation::Rollback, Reconciliation::Algebraic]
+        .into_iter()
+        .filter(|strategy| analyses.iter().any(|x| x.reconciliation == *strategy))
+        .collect::<Vec<_>>();
+
+    let ticks = analyses
+        .iter()
+        .flat_map(|x| &x.samples)
+        .map(|x| x.tick as f32);
+    let min_tick = ticks.clone().fold(f32::INFINITY, f32::min);
+    let max_tick = ticks.fold(f32::NEG_INFINITY, f32::max);
+    let (min_tick, max_tick) = if min_tick < max_tick {
+        (min_tick, max_tick)
+    } else {
+        (0.0, 1.0)
+    };
+
+    let legend_height = analyses.len() as f32 * LEGEND_LINE_HEIGHT;
+    let width = MARGIN + strategies.len().max(1) as f32 * (PANEL_WIDTH + MARGIN);
+    let height = MARGIN + METRICS.len() as f32 * (PANEL_HEIGHT + MARGIN) + legend_height;
+
+    let mut svg = String::new();
+
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="sans-serif" font-size="12">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="{width}" height="{height}" fill="white"/>"#
+    );
+
+    for (row, metric) in METRICS.iter().enumerate() {
+        let max_value = analyses
+            .iter()
+            .flat_map(|x| &x.samples)
+            .filter_map(metric.value)
+            .fold(0.0, f32::max)
+            .max(f32::EPSILON);
+
+        for (column, strategy) in strategies.iter().enumerate() {
+            let x0 = MARGIN + column as f32 * (PANEL_WIDTH + MARGIN);
+            let y0 = MARGIN + row as f32 * (PANEL_HEIGHT + MARGIN);
+
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{x0}" y="{y0}" width="{PANEL_WIDTH}" height="{PANEL_HEIGHT}" fill="none" stroke="black"/>"#
+            );
+            let _ = writeln!(
+                svg,
+                r#"<text x="{x0}" y="{}">{} ({:?})</text>"#,
+                y0 - 6.0,
+                metric.name,
+                strategy
+            );
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="end">{:.2}</text>"#,
+                x0 - 4.0,
+                y0 + 10.0,
+                max_value
+            );
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="end">0</text>"#,
+                x0 - 4.0,
+                y0 + PANEL_HEIGHT
+            );
+            let _ = writeln!(
+                svg,
+                r#"<text x="{x0}" y="{}">{min_tick}</text>"#,
+                y0 + PANEL_HEIGHT + 14.0
+            );
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="end">tick {max_tick}</text>"#,
+                x0 + PANEL_WIDTH,
+                y0 + PANEL_HEIGHT + 14.0
+            );
+
+            for (index, analysis) in analyses.iter().enumerate() {
+                if analysis.reconciliation != *strategy {
+                    continue;
+                }
+
+                let color = COLORS[index % COLORS.len()];
+
+                // Ticks without a value split the line.
+                let mut segment = Vec::new();
+                for sample in &analysis.samples {
+                    match (metric.value)(sample) {
+                        Some(value) => {
+                            let x = x0
+                                + (sample.tick as f32 - min_tick) / (max_tick - min_tick)
+                                    * PANEL_WIDTH;
+                            let y = y0 + PANEL_HEIGHT - value / max_value * PANEL_HEIGHT;
+                            segment.push(format!("{:.1},{:.1}", x, y));
+                        }
+                        None => polyline(&mut svg, &mut segment, color),
+                    }
+                }
+                polyline(&mut svg, &mut segment, color);
+            }
+        }
+    }
+
+    let legend_y = MARGIN + METRICS.len() as f32 * (PANEL_HEIGHT + MARGIN);
+
+    for (index, analysis) in analyses.iter().enumerate() {
+        let y = legend_y + index as f32 * LEGEND_LINE_HEIGHT;
+        let color = COLORS[index % COLORS.len()];
+
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{MARGIN}" y="{}" width="20" height="4" fill="{color}"/>"#,
+            y - 4.0
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{y}">{} ({:?})</text>"#,
+            MARGIN + 26.0,
+            analysis.name,
+            analysis.reconciliation
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn polyline(svg: &mut String, points: &mut Vec<String>, color: &str) {
+    if points.is_empty() {
+        return;
+    }
+
+    let _ = writeln!(
+        svg,
+        r#"<polyline points="{}" fill="none" stroke="{color}" stroke-width="1"/>"#,
+        points.join(" ")
+    );
+    points.clear();
+}
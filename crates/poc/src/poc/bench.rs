@@ -0,0 +1,154 @@
+//! Fixtures for the benchmarks in `benches/`.
+
+use std::{collections::VecDeque, marker::PhantomData};
+
+use avian2d::prelude::{AngularVelocity, Collider, LinearVelocity, RigidBody};
+use bevy::{ecs::system::System, prelude::*};
+use bevy_replicon::shared::replicon_tick::RepliconTick;
+use rand::{rngs::StdRng, SeedableRng};
+
+use super::{
+    algebraic::{system_algebraic_correct, PredictedHistory, RandomAlgebraic},
+    movement::{MovementConfig, MovementController},
+    prediction::PredictionWorld,
+    simulation_app, tick_duration, MovementInput, Predicted, PredictedMemory, SimulationMode,
+    SERVER_CONFIG_HZ,
+};
+
+/// Predicted players standing on the terrain, replaying a fixed list of
+/// inputs in a [`PredictionWorld`] like `system_predict` does after a correction.
+pub struct RollbackFixture {
+    app: App,
+    prediction: PredictionWorld,
+    entities: Vec<Entity>,
+    initial: Vec<(Transform, LinearVelocity, AngularVelocity)>,
+    inputs: Vec<MovementInput>,
+}
+
+impl RollbackFixture {
+    pub fn new(entities: usize, inputs: usize) -> Self {
+        let gravity = Vec2::new(0.0, -1000.0);
+        let mut app = simulation_app(gravity);
+        let world = app.world_mut();
+
+        world.spawn((
+            Transform::from_xyz(100.0, -100.0, 0.0),
+            RigidBody::Kinematic,
+            Collider::rectangle(500.0, 20.0),
+        ));
+
+        let entities = (0..entities)
+            .map(|index| {
+                world
+                    .spawn((
+                        MovementController::new(
+                            Collider::capsule(10.0, 30.0),
+                            MovementConfig::default(),
+                        ),
+                        Transform::from_xyz(index as f32 * 30.0 - 100.0, -65.0, 0.0),
+                        Predicted,
+                    ))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+
+        let inputs = (0..inputs)
+            .map(|index| MovementInput {
+                direction: if index / 10 % 2 == 0 { 1.0 } else { -1.0 },
+                jump: index % 20 == 0,
+                tick: RepliconTick::new(index as u32),
+            })
+            .collect();
+
+        let mut query = world.query::<(&Transform, &LinearVelocity, &AngularVelocity)>();
+        let initial = entities
+            .iter()
+            .map(|&entity| {
+                let (transform, linear_velocity, angular_velocity) =
+                    query.get(world, entity).unwrap();
+                (*transform, *linear_velocity, *angular_velocity)
+            })
+            .collect();
+
+        Self {
+            app,
+            prediction: PredictionWorld::new(gravity, SimulationMode::Standard),
+            entities,
+            initial,
+            inputs,
+        }
+    }
+
+    /// Restores the initial state and replays all inputs.
+    pub fn rollback(&mut self) {
+        let world = self.app.world_mut();
+
+        for (&entity, initial) in self.entities.iter().zip(&self.initial) {
+            world.entity_mut(entity).insert(*initial);
+        }
+
+        self.prediction.replay(
+            world,
+            &self.entities,
+            &self.inputs,
+            tick_duration(SERVER_CONFIG_HZ),
+            0,
+        );
+    }
+}
+
+/// Predicted entities that each received one server value and have a history
+/// of predictions to correct.
+pub struct AlgebraicFixture<C: RandomAlgebraic> {
+    world: World,
+    system: Box<dyn System<In = (), Out = ()>>,
+    entities: Vec<Entity>,
+    history: usize,
+    rng: StdRng,
+    marker: PhantomData<C>,
+}
+
+impl<C: RandomAlgebraic> AlgebraicFixture<C> {
+    pub fn new(entities: usize, history: usize) -> Self {
+        let mut world = World::new();
+        let entities = (0..entities).map(|_| world.spawn(Predicted).id()).collect();
+
+        let mut system = Box::new(IntoSystem::into_system(system_algebraic_correct::<C>));
+        system.initialize(&mut world);
+
+        let mut fixture = Self {
+            world,
+            system,
+            entities,
+            history,
+            rng: StdRng::seed_from_u64(0),
+            marker: PhantomData,
+        };
+
+        fixture.refill();
+        fixture
+    }
+
+    /// Replaces the values consumed by [`AlgebraicFixture::correct`].
+    pub fn refill(&mut self) {
+        for &entity in &self.entities {
+            let memory = PredictedMemory {
+                values: VecDeque::from([(C::random(&mut self.rng), RepliconTick::new(1))]),
+            };
+
+            let history = PredictedHistory {
+                values: (1..=self.history as u32)
+                    .map(|tick| (C::random(&mut self.rng), RepliconTick::new(tick)))
+                    .collect(),
+            };
+
+            self.world
+                .entity_mut(entity)
+                .insert((C::random(&mut self.rng), memory, history));
+        }
+    }
+
+    pub fn correct(&mut self) {
+        self.system.run((), &mut self.world);
+    }
+}
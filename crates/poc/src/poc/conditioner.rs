@@ -0,0 +1,438 @@
+use std::{collections::HashMap, f32::consts::TAU, time::Duration};
+
+use bevy::prelude::*;
+use bevy_replicon::{
+    client::ClientSet,
+    prelude::{
+        client_connected, client_just_disconnected, server_running, Channel, ConnectedClient,
+        RepliconChannels, RepliconClient, RepliconServer,
+    },
+    server::ServerSet,
+};
+use bytes::Bytes;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Conditions applied to every message passing through the conditioner. Latency is
+/// applied once per direction, so a client and a server with 50ms each add 200ms
+/// to the round trip time.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ConditionerConfig {
+    /// Seed for reproducible runs, random if not set.
+    pub seed: Option<u64>,
+    pub default: LinkConditions,
+    /// Overrides for individual channel ids.
+    pub channels: HashMap<usize, LinkConditions>,
+}
+
+impl ConditionerConfig {
+    pub fn is_passthrough(&self) -> bool {
+        self.default.is_passthrough() && self.channels.values().all(|x| x.is_passthrough())
+    }
+
+    fn conditions(&self, channel_id: usize) -> &LinkConditions {
+        self.channels.get(&channel_id).unwrap_or(&self.default)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct LinkConditions {
+    /// One way delay in milliseconds.
+    pub latency: Distribution,
+    /// Probability that a message is dropped. Only applies to unreliable channels.
+    pub loss: f32,
+    /// Probability that a message is sent twice. Only applies to unreliable channels.
+    pub duplicate: f32,
+    /// Probability that a message is held back by `reorder_delay`, so later messages
+    /// overtake it. Does not apply to ordered channels.
+    pub reorder: f32,
+    /// Additional delay in milliseconds for reordered messages.
+    pub reorder_delay: Distribution,
+}
+
+impl LinkConditions {
+    fn is_passthrough(&self) -> bool {
+        self.latency.is_zero() && self.loss <= 0.0 && self.duplicate <= 0.0 && self.reorder <= 0.0
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum Distribution {
+    Constant(f32),
+    Uniform { min: f32, max: f32 },
+    Normal { mean: f32, std_dev: f32 },
+}
+
+impl Default for Distribution {
+    fn default() -> Self {
+        Distribution::Constant(0.0)
+    }
+}
+
+impl Distribution {
+    pub fn is_zero(&self) -> bool {
+        match *self {
+            Distribution::Constant(value) => value <= 0.0,
+            Distribution::Uniform { max, .. } => max <= 0.0,
+            Distribution::Normal { mean, std_dev } => mean <= 0.0 && std_dev <= 0.0,
+        }
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> f32 {
+        let value = match *self {
+            Distribution::Constant(value) => value,
+            Distribution::Uniform { min, max } if min < max => rng.random_range(min..max),
+            Distribution::Uniform { min, .. } => min,
+            Distribution::Normal { mean, std_dev } => {
+                // Box-Muller transform
+                let u1 = rng.random::<f32>().max(f32::MIN_POSITIVE);
+                let u2 = rng.random::<f32>();
+                mean + std_dev * (-2.0 * u1.ln()).sqrt() * (TAU * u2).cos()
+            }
+        };
+
+        value.max(0.0)
+    }
+
+    fn sample_duration(&self, rng: &mut impl Rng) -> Duration {
+        Duration::from_secs_f32(self.sample(rng) / 1000.0)
+    }
+}
+
+struct DelayedMessage {
+    release: Duration,
+    client_entity: Entity,
+    channel_id: usize,
+    message: Bytes,
+}
+
+/// Queue of messages in one direction.
+#[derive(Default)]
+struct Link {
+    messages: Vec<DelayedMessage>,
+    last_ordered_release: HashMap<(Entity, usize), Duration>,
+}
+
+impl Link {
+    fn push(
+        &mut self,
+        now: Duration,
+        config: &ConditionerConfig,
+        rng: &mut StdRng,
+        channel: Channel,
+        (client_entity, channel_id): (Entity, usize),
+        message: Bytes,
+    ) {
+        let conditions = config.conditions(channel_id);
+        let reliable = !matches!(channel, Channel::Unreliable);
+
+        if !reliable && rng.random::<f32>() < conditions.loss {
+            return;
+        }
+
+        let copies = if !reliable && rng.random::<f32>() < conditions.duplicate {
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            let mut release = now + conditions.latency.sample_duration(rng);
+
+            if matches!(channel, Channel::Ordered) {
+                // Ordered channels can be delayed but never overtake each other.
+                let last = self
+                    .last_ordered_release
+                    .entry((client_entity, channel_id))
+                    .or_default();
+
+                release = release.max(*last);
+                *last = release;
+            } else if rng.random::<f32>() < conditions.reorder {
+                release += conditions.reorder_delay.sample_duration(rng);
+            }
+
+            self.messages.push(DelayedMessage {
+                release,
+                client_entity,
+                channel_id,
+                message: message.clone(),
+            });
+        }
+    }
+
+    fn release(&mut self, now: Duration) -> Vec<DelayedMessage> {
+        // Stable sort so messages with the same release time keep their order.
+        self.messages.sort_by_key(|x| x.release);
+
+        let count = self
+            .messages
+            .iter()
+            .position(|x| x.release > now)
+            .unwrap_or(self.messages.len());
+
+        self.messages.drain(0..count).collect()
+    }
+
+    fn clear(&mut self) {
+        self.messages.clear();
+        self.last_ordered_release.clear();
+    }
+}
+
+#[derive(Resource)]
+pub struct Conditioner {
+    config: ConditionerConfig,
+    rng: StdRng,
+    incoming: Link,
+    outgoing: Link,
+}
+
+impl Conditioner {
+    pub fn new(config: ConditionerConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+
+        Self {
+            config,
+            rng,
+            incoming: Link::default(),
+            outgoing: Link::default(),
+        }
+    }
+}
+
+/// Sits between replicon and the transport backend and delays, drops, duplicates
+/// and reorders messages according to a [`ConditionerConfig`]. Uses virtual time,
+/// so manually stepped apps get a deterministic latency.
+pub enum ConditionerPlugin {
+    Client(ConditionerConfig),
+    Server(ConditionerConfig),
+}
+
+#[derive(SystemSet, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+enum ConditionerSet {
+    Receive,
+    Send,
+}
+
+impl Plugin for ConditionerPlugin {
+    fn build(&self, app: &mut App) {
+        match self {
+            ConditionerPlugin::Client(config) => {
+                if config.is_passthrough() {
+                    return;
+                }
+
+                app.insert_resource(Conditioner::new(config.clone()))
+                    .configure_sets(
+                        PreUpdate,
+                        ConditionerSet::Receive
+                            .after(ClientSet::ReceivePackets)
+                            .before(ClientSet::Receive),
+                    )
+                    .configure_sets(
+                        PostUpdate,
+                        ConditionerSet::Send
+                            .after(ClientSet::Send)
+                            .before(ClientSet::SendPackets),
+                    )
+                    .add_systems(
+                        PreUpdate,
+                        (
+                            system_client_clear.run_if(client_just_disconnected),
+                            system_client_receive.run_if(client_connected),
+                        )
+                            .chain()
+                            .in_set(ConditionerSet::Receive),
+                    )
+                    .add_systems(
+                        PostUpdate,
+                        system_client_send
+                            .run_if(client_connected)
+                            .in_set(ConditionerSet::Send),
+                    );
+            }
+            ConditionerPlugin::Server(config) => {
+                if config.is_passthrough() {
+                    return;
+                }
+
+                app.insert_resource(Conditioner::new(config.clone()))
+                    .configure_sets(
+                        PreUpdate,
+                        ConditionerSet::Receive
+                            .after(ServerSet::ReceivePackets)
+                            .before(ServerSet::Receive),
+                    )
+                    .configure_sets(
+                        PostUpdate,
+                        ConditionerSet::Send
+                            .after(ServerSet::Send)
+                            .before(ServerSet::SendPackets),
+                    )
+                    .add_systems(
+                        PreUpdate,
+                        system_server_receive
+                            .run_if(server_running)
+                            .in_set(ConditionerSet::Receive),
+                    )
+                    .add_systems(
+                        PostUpdate,
+                        system_server_send
+                            .run_if(server_running)
+                            .in_set(ConditionerSet::Send),
+                    );
+            }
+        }
+    }
+}
+
+fn system_client_clear(mut conditioner: ResMut<Conditioner>) {
+    conditioner.incoming.clear();
+    conditioner.outgoing.clear();
+}
+
+fn system_client_receive(
+    time: Res<Time>,
+    channels: Res<RepliconChannels>,
+    mut client: ResMut<RepliconClient>,
+    mut conditioner: ResMut<Conditioner>,
+) {
+    let now = time.elapsed();
+    let Conditioner {
+        config,
+        rng,
+        incoming,
+        ..
+    } = conditioner.as_mut();
+
+    for (channel_id, channel) in channels.server_channels().iter().enumerate() {
+        let messages = client.receive(channel_id).collect::<Vec<_>>();
+
+        for message in messages {
+            incoming.push(
+                now,
+                config,
+                rng,
+                *channel,
+                (Entity::PLACEHOLDER, channel_id),
+                message,
+            );
+        }
+    }
+
+    for delayed in incoming.release(now) {
+        client.insert_received(delayed.channel_id, delayed.message);
+    }
+}
+
+fn system_client_send(
+    time: Res<Time>,
+    channels: Res<RepliconChannels>,
+    mut client: ResMut<RepliconClient>,
+    mut conditioner: ResMut<Conditioner>,
+) {
+    let now = time.elapsed();
+    let Conditioner {
+        config,
+        rng,
+        outgoing,
+        ..
+    } = conditioner.as_mut();
+
+    let messages = client.drain_sent().collect::<Vec<_>>();
+
+    for (channel_id, message) in messages {
+        let channel = channels.client_channels()[channel_id];
+        outgoing.push(
+            now,
+            config,
+            rng,
+            channel,
+            (Entity::PLACEHOLDER, channel_id),
+            message,
+        );
+    }
+
+    for delayed in outgoing.release(now) {
+        client.send(delayed.channel_id, delayed.message);
+    }
+}
+
+fn system_server_receive(
+    time: Res<Time>,
+    channels: Res<RepliconChannels>,
+    mut server: ResMut<RepliconServer>,
+    mut conditioner: ResMut<Conditioner>,
+    query_clients: Query<(), With<ConnectedClient>>,
+) {
+    let now = time.elapsed();
+    let Conditioner {
+        config,
+        rng,
+        incoming,
+        ..
+    } = conditioner.as_mut();
+
+    for (channel_id, channel) in channels.client_channels().iter().enumerate() {
+        let messages = server.receive(channel_id).collect::<Vec<_>>();
+
+        for (client_entity, message) in messages {
+            incoming.push(
+                now,
+                config,
+                rng,
+                *channel,
+                (client_entity, channel_id),
+                message,
+            );
+        }
+    }
+
+    for delayed in incoming.release(now) {
+        if query_clients.contains(delayed.client_entity) {
+            server.insert_received(delayed.client_entity, delayed.channel_id, delayed.message);
+        }
+    }
+}
+
+fn system_server_send(
+    time: Res<Time>,
+    channels: Res<RepliconChannels>,
+    mut server: ResMut<RepliconServer>,
+    mut conditioner: ResMut<Conditioner>,
+    query_clients: Query<(), With<ConnectedClient>>,
+) {
+    let now = time.elapsed();
+    let Conditioner {
+        config,
+        rng,
+        outgoing,
+        ..
+    } = conditioner.as_mut();
+
+    let messages = server.drain_sent().collect::<Vec<_>>();
+
+    for (client_entity, channel_id, message) in messages {
+        let channel = channels.server_channels()[channel_id];
+        outgoing.push(
+            now,
+            config,
+            rng,
+            channel,
+            (client_entity, channel_id),
+            message,
+        );
+    }
+
+    for delayed in outgoing.release(now) {
+        if query_clients.contains(delayed.client_entity) {
+            server.send(delayed.client_entity, delayed.channel_id, delayed.message);
+        }
+    }
+}
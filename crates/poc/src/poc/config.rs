@@ -0,0 +1,51 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+};
+
+use anyhow::Result;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::conditioner::ConditionerConfig;
+
+pub const PORT: u16 = 24325;
+
+/// Network settings of this process. Loaded from a RON file and
+/// overridden by environment variables or command line arguments.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// Address the server endpoint listens on.
+    pub bind: SocketAddr,
+    /// Address of the server the client connects to.
+    pub connect: SocketAddr,
+    /// Simulated network conditions applied by this process.
+    pub conditioner: ConditionerConfig,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), PORT),
+            connect: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), PORT),
+            conditioner: ConditionerConfig::default(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(ron::from_str(&content)?)
+    }
+
+    /// The client has to bind a socket of the same family as the server address.
+    pub fn client_local_ip(&self) -> IpAddr {
+        match self.connect {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        }
+    }
+}
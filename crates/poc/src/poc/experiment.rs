@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    process,
+    time::Duration,
+};
+
+use anyhow::Result;
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{
+    conditioner::{ConditionerConfig, Distribution, LinkConditions},
+    config::NetworkConfig,
+    loopback::{headless_app, LoopbackNetwork},
+    trace::{read_trace, Trace, Tracer},
+    ClientMode, InputScript, MovementInput, PocType, Reconciliation, ReconciliationStats,
+};
+
+/// Prediction errors below this distance count as converged.
+const CONVERGENCE_TOLERANCE: f32 = 1.0;
+
+/// Share of the ticks in which the bots move, they stand still afterwards.
+const ACTIVE_SHARE: f32 = 2.0 / 3.0;
+
+/// Values of every parameter, each combination is run once.
+pub struct ExperimentMatrix {
+    pub strategies: Vec<Reconciliation>,
+    /// One way latency in milliseconds, applied by the client in both directions.
+    pub latencies: Vec<f32>,
+    pub losses: Vec<f32>,
+    pub tick_rates: Vec<f32>,
+    /// Number of bot clients, each predicting its own player.
+    pub clients: Vec<usize>,
+    pub ticks: usize,
+    pub seed: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ExperimentCase {
+    pub strategy: Reconciliation,
+    pub latency: f32,
+    pub loss: f32,
+    pub hz: f32,
+    pub clients: usize,
+}
+
+pub struct ExperimentResult {
+    pub case: ExperimentCase,
+    /// Time all clients spent correcting their prediction.
+    pub reconciliation_time: Duration,
+    /// Bytes per second sent by the server to all clients.
+    pub server_bandwidth: f32,
+    /// Bytes per second sent by all clients to the server.
+    pub client_bandwidth: f32,
+    /// Distance between prediction and server state whenever a server state arrives.
+    pub mean_error: f32,
+    pub max_error: f32,
+    /// Seconds after the bots stopped until all predictions matched the server,
+    /// `None` if they did not converge before the end of the run.
+    pub convergence: Option<f32>,
+}
+
+impl ExperimentMatrix {
+    pub fn cases(&self) -> Vec<ExperimentCase> {
+        let mut cases = Vec::new();
+
+        for &strategy in &self.strategies {
+            for &latency in &self.latencies {
+                for &loss in &self.losses {
+                    for &hz in &self.tick_rates {
+                        for &clients in &self.clients {
+                            cases.push(ExperimentCase {
+                                strategy,
+                                latency,
+                                loss,
+                                hz,
+                                clients,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        cases
+    }
+}
+
+/// Runs every case of the matrix, prints the results and writes them as CSV to `out`.
+pub fn run(matrix: &ExperimentMatrix, out: Option<&Path>) -> Result<Vec<ExperimentResult>> {
+    let cases = matrix.cases();
+    let mut results = Vec::new();
+
+    println!(
+        "{:>9} {:>8} {:>5} {:>5} {:>8} {:>10} {:>10} {:>10} {:>8} {:>8} {:>8}",
+        "strategy",
+        "latency",
+        "loss",
+        "hz",
+        "clients",
+        "cpu (ms)",
+        "down B/s",
+        "up B/s",
+        "mean err",
+        "max err",
+        "converge"
+    );
+
+    for (index, case) in cases.iter().enumerate() {
+        let result = run_case(*case, matrix.ticks, matrix.seed.wrapping_add(index as u64))?;
+
+        println!(
+            "{:>9} {:>8} {:>5} {:>5} {:>8} {:>10.3} {:>10.0} {:>10.0} {:>8.3} {:>8.3} {:>8}",
+            format!("{:?}", case.strategy),
+            case.latency,
+            case.loss,
+            case.hz,
+            case.clients,
+            result.reconciliation_time.as_secs_f64() * 1000.0,
+            result.server_bandwidth,
+            result.client_bandwidth,
+            result.mean_error,
+            result.max_error,
+            result
+                .convergence
+                .map(|x| format!("{:.2}s", x))
+                .unwrap_or_else(|| "never".to_string())
+        );
+
+        results.push(result);
+    }
+
+    if let Some(out) = out {
+        write_csv(out, &results)?;
+        println!("Wrote results to {}", out.display());
+    }
+
+    Ok(results)
+}
+
+/// Connects `case.clients` scripted bots to a server over the loopback
+/// transport and runs them for `ticks` ticks.
+pub fn run_case(case: ExperimentCase, ticks: usize, seed: u64) -> Result<ExperimentResult> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let active_ticks = (ticks as f32 * ACTIVE_SHARE) as usize;
+
+    let mut server = headless_app(PocType::Server, NetworkConfig::default());
+    server.insert_resource(Time::<Fixed>::from_hz(case.hz as f64));
+
+    let mut loopback = LoopbackNetwork::new(server);
+    let mut trace_paths = Vec::new();
+
+    for index in 0..case.clients {
+        let path =
+            env::temp_dir().join(format!("poc-experiment-{}-{}.trace", process::id(), index));
+
+        let conditioner = ConditionerConfig {
+            seed: Some(rng.random()),
+            default: LinkConditions {
+                latency: Distribution::Constant(case.latency),
+                loss: case.loss,
+                ..default()
+            },
+            ..default()
+        };
+
+        let mut client = headless_app(
+            PocType::Client(ClientMode::Scripted),
+            NetworkConfig {
+                conditioner,
+                ..default()
+            },
+        );
+        client
+            .insert_resource(Time::<Fixed>::from_hz(case.hz as f64))
+            .insert_resource(bot_script(&mut rng, active_ticks))
+            .insert_resource(case.strategy)
+            .insert_resource(Tracer::create(&path)?);
+
+        loopback.connect(client);
+        trace_paths.push(path);
+    }
+
+    loopback.run(ticks);
+
+    let reconciliation_time = loopback
+        .clients
+        .iter()
+        .map(|x| x.app.world().resource::<ReconciliationStats>().time)
+        .sum();
+
+    let seconds = ticks as f32 / case.hz;
+    let server_bandwidth = loopback.server_bytes as f32 / seconds;
+    let client_bandwidth = loopback.client_bytes as f32 / seconds;
+
+    // Dropping the apps flushes the traces.
+    drop(loopback);
+
+    let mut errors = Vec::new();
+    let mut convergence = Some(0.0f32);
+
+    for path in &trace_paths {
+        let trace = read_trace(path);
+        fs::remove_file(path)?;
+        let trace = trace?;
+
+        let trace_errors = prediction_errors(&trace);
+
+        convergence = match (convergence, converged_after(&trace_errors, active_ticks)) {
+            (Some(a), Some(b)) => Some(a.max(b as f32 / case.hz)),
+            _ => None,
+        };
+
+        errors.extend(trace_errors.into_iter().map(|(_, error)| error));
+    }
+
+    Ok(ExperimentResult {
+        case,
+        reconciliation_time,
+        server_bandwidth,
+        client_bandwidth,
+        mean_error: errors.iter().sum::<f32>() / errors.len().max(1) as f32,
+        max_error: errors.iter().copied().fold(0.0, f32::max),
+        convergence,
+    })
+}
+
+/// Random inputs like [`ClientMode::Bot`], but reproducible from the seed.
+fn bot_script(rng: &mut impl Rng, active_ticks: usize) -> InputScript {
+    let mut inputs = Vec::new();
+
+    while inputs.len() < active_ticks {
+        let input = MovementInput {
+            direction: rng.random_range(-1.0..=1.0),
+            jump: rng.random_bool(0.2),
+            ..default()
+        };
+        let length = rng.random_range(5..60).min(active_ticks - inputs.len());
+
+        inputs.extend(std::iter::repeat_n(input, length));
+    }
+
+    inputs.push(MovementInput::default());
+
+    InputScript { inputs }
+}
+
+/// Difference between the predicted and the acknowledged position for every
+/// tick whose server state arrived.
+fn prediction_errors(trace: &Trace) -> Vec<(u32, f32)> {
+    let predicted = trace
+        .ticks
+        .iter()
+        .map(|x| (x.tick, x.predicted.position))
+        .collect::<HashMap<_, _>>();
+
+    let mut errors = trace
+        .ticks
+        .iter()
+        .flat_map(|x| &x.acks)
+        .filter_map(|ack| Some((ack.tick, predicted.get(&ack.tick)?.distance(ack.position))))
+        .collect::<Vec<_>>();
+
+    errors.sort_by_key(|(tick, _)| *tick);
+    errors
+}
+
+/// Ticks after `from` until all later errors stay below the tolerance.
+fn converged_after(errors: &[(u32, f32)], from: usize) -> Option<u32> {
+    let from = from as u32;
+    let later = errors
+        .iter()
+        .filter(|(tick, _)| *tick >= from)
+        .collect::<Vec<_>>();
+
+    if later
+        .last()
+        .is_none_or(|(_, error)| *error > CONVERGENCE_TOLERANCE)
+    {
+        return None;
+    }
+
+    Some(
+        later
+            .into_iter()
+            .filter(|(_, error)| *error > CONVERGENCE_TOLERANCE)
+            .map(|(tick, _)| tick + 1 - from)
+            .max()
+            .unwrap_or(0),
+    )
+}
+
+fn write_csv(path: &Path, results: &[ExperimentResult]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writeln!(
+        writer,
+        "strategy,latency_ms,loss,hz,clients,reconciliation_ms,server_bytes_per_second,client_bytes_per_second,mean_error,max_error,convergence_s"
+    )?;
+
+    for result in results {
+        let case = result.case;
+
+        writeln!(
+            writer,
+            "{:?},{},{},{},{},{},{},{},{},{},{}",
+            case.strategy,
+            case.latency,
+            case.loss,
+            case.hz,
+            case.clients,
+            result.reconciliation_time.as_secs_f64() * 1000.0,
+            result.server_bandwidth,
+            result.client_bandwidth,
+            result.mean_error,
+            result.max_error,
+            result
+                .convergence
+                .map(|x| x.to_string())
+                .unwrap_or_default()
+        )?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
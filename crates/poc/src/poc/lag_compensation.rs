@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+
+use avian2d::prelude::{Collider, CollisionLayers, Position, Rotation, SpatialQueryPipeline};
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_replicon::{
+    prelude::Replicated, server::server_tick::ServerTick, shared::replicon_tick::RepliconTick,
+};
+use serde::{Deserialize, Serialize};
+
+use super::InputTickOffset;
+
+/// How far the server rewinds colliders for the queries of a client.
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct LagCompensationConfig {
+    /// Queries never see colliders older than this, so clients with a high
+    /// latency cannot hit targets that moved away long ago.
+    pub max_rewind_ticks: u32,
+    /// Ticks that clients show remote entities behind their predicted tick,
+    /// announced to them in `IndividualServerConfig`.
+    pub interpolation_ticks: u32,
+}
+
+impl Default for LagCompensationConfig {
+    fn default() -> Self {
+        Self {
+            max_rewind_ticks: 20,
+            interpolation_ticks: 6,
+        }
+    }
+}
+
+impl LagCompensationConfig {
+    /// Server tick of the colliders a client saw when it predicted
+    /// `simulation_tick`, clamped to the rewind window ending at `server_tick`.
+    pub fn rewind_tick(&self, server_tick: u32, simulation_tick: u32) -> u32 {
+        simulation_tick
+            .saturating_sub(self.interpolation_ticks)
+            .clamp(
+                server_tick.saturating_sub(self.max_rewind_ticks),
+                server_tick,
+            )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ColliderPose {
+    pub tick: u32,
+    pub transform: Transform,
+    pub collider: Collider,
+    pub layers: CollisionLayers,
+}
+
+/// Collider poses of a replicated entity on the server for the ticks in the
+/// rewind window, newest last.
+#[derive(Component, Clone, Default, Debug)]
+pub struct ColliderHistory {
+    pub poses: VecDeque<ColliderPose>,
+}
+
+impl ColliderHistory {
+    /// Newest pose at or before `tick`.
+    pub fn at(&self, tick: u32) -> Option<&ColliderPose> {
+        self.poses.iter().rev().find(|x| x.tick <= tick)
+    }
+}
+
+/// Spatial queries against the colliders as they were at an earlier server
+/// tick, e.g. to check a shot against the targets the shooter saw.
+#[derive(SystemParam)]
+pub struct LagCompensation<'w, 's> {
+    config: Res<'w, LagCompensationConfig>,
+    server_tick: Res<'w, ServerTick>,
+    query: Query<'w, 's, (Entity, &'static ColliderHistory)>,
+    query_offset: Query<'w, 's, &'static InputTickOffset>,
+}
+
+impl LagCompensation<'_, '_> {
+    /// Server tick of the colliders `player` saw when it sent the input of
+    /// `input_tick`. Client ticks are converted with the [`InputTickOffset`] of
+    /// the player, `None` if it has none.
+    pub fn rewind_tick(&self, player: Entity, input_tick: RepliconTick) -> Option<u32> {
+        let offset = self.query_offset.get(player).ok()?;
+        let simulation_tick = input_tick.get().wrapping_add(offset.0);
+
+        Some(
+            self.config
+                .rewind_tick(self.server_tick.get(), simulation_tick),
+        )
+    }
+
+    /// Query pipeline with the colliders at `tick`, clamped to the rewind
+    /// window. Entities spawned after `tick` are left out.
+    pub fn rewind(&self, tick: u32) -> SpatialQueryPipeline {
+        let server_tick = self.server_tick.get();
+        let tick = tick.clamp(
+            server_tick.saturating_sub(self.config.max_rewind_ticks),
+            server_tick,
+        );
+
+        let poses = self
+            .query
+            .iter()
+            .filter_map(|(entity, history)| {
+                let pose = history.at(tick)?;
+                let (_, _, angle) = pose.transform.rotation.to_euler(EulerRot::XYZ);
+                let position = Position(pose.transform.translation.truncate());
+                let rotation = Rotation::radians(angle);
+
+                Some((entity, position, rotation, pose))
+            })
+            .collect::<Vec<_>>();
+
+        let mut pipeline = SpatialQueryPipeline::default();
+        pipeline.update(poses.iter().map(|(entity, position, rotation, pose)| {
+            (*entity, position, rotation, &pose.collider, &pose.layers)
+        }));
+
+        pipeline
+    }
+}
+
+/// Stores the pose of every replicated collider at the current server tick.
+pub(super) fn system_record_collider_history(
+    mut commands: Commands,
+    config: Res<LagCompensationConfig>,
+    server_tick: Res<ServerTick>,
+    query: Query<
+        (
+            Entity,
+            &Transform,
+            &Collider,
+            Option<&CollisionLayers>,
+            Option<&mut ColliderHistory>,
+        ),
+        With<Replicated>,
+    >,
+) {
+    let tick = server_tick.get();
+    let oldest = tick.saturating_sub(config.max_rewind_ticks);
+
+    for (entity, transform, collider, layers, history) in query {
+        let pose = ColliderPose {
+            tick,
+            transform: *transform,
+            collider: collider.clone(),
+            layers: layers.copied().unwrap_or_default(),
+        };
+
+        let Some(mut history) = history else {
+            commands.entity(entity).insert(ColliderHistory {
+                poses: VecDeque::from([pose]),
+            });
+            continue;
+        };
+
+        history.poses.push_back(pose);
+
+        // The newest pose before the window is still the pose at its start.
+        while history.poses.get(1).is_some_and(|x| x.tick <= oldest) {
+            history.poses.pop_front();
+        }
+    }
+}
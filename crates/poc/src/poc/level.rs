@@ -0,0 +1,208 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use avian2d::prelude::{Collider, RigidBody};
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::mesh::{Indices, PrimitiveTopology},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{platform::MovingPlatform, Terrain};
+
+/// Static terrain, moving platforms and spawn points of a level. Loaded by the
+/// server from a RON file, clients receive the terrain through replication.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Level {
+    pub terrain: Vec<TerrainPiece>,
+    pub platforms: Vec<PlatformPiece>,
+    pub spawns: Vec<Vec2>,
+    /// Players falling below this height are respawned.
+    pub kill_plane: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TerrainPiece {
+    pub position: Vec2,
+    pub shape: TerrainShape,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlatformPiece {
+    pub shape: TerrainShape,
+    pub platform: MovingPlatform,
+}
+
+/// Shape of a terrain piece, relative to its position.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TerrainShape {
+    Rectangle {
+        width: f32,
+        height: f32,
+    },
+    /// Convex polygon, e.g. a triangle for a slope.
+    Polygon {
+        vertices: Vec<Vec2>,
+    },
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            terrain: vec![TerrainPiece {
+                position: Vec2::new(100.0, -100.0),
+                shape: TerrainShape::Rectangle {
+                    width: 500.0,
+                    height: 20.0,
+                },
+            }],
+            platforms: vec![PlatformPiece {
+                shape: TerrainShape::Rectangle {
+                    width: 100.0,
+                    height: 16.0,
+                },
+                platform: MovingPlatform {
+                    start: Vec2::new(450.0, -60.0),
+                    end: Vec2::new(650.0, 20.0),
+                    period: 90,
+                },
+            }],
+            spawns: vec![Vec2::new(0.0, 100.0)],
+            kill_plane: -1000.0,
+        }
+    }
+}
+
+impl Level {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let level: Self = ron::from_str(&content)?;
+
+        if level.spawns.is_empty() {
+            bail!("{}: level has no spawn points", path.display());
+        }
+
+        let shapes = level
+            .terrain
+            .iter()
+            .map(|x| ("terrain", &x.shape))
+            .chain(level.platforms.iter().map(|x| ("platform", &x.shape)));
+
+        for (index, (kind, shape)) in shapes.enumerate() {
+            shape
+                .validate()
+                .with_context(|| format!("{}: shape {} ({})", path.display(), index, kind))?;
+        }
+
+        Ok(level)
+    }
+}
+
+/// Hands out the spawn points of the [`Level`] in turn.
+#[derive(Resource, Default)]
+pub(super) struct SpawnPoints {
+    next: usize,
+}
+
+impl SpawnPoints {
+    pub(super) fn next(&mut self, level: &Level) -> Vec2 {
+        let spawn = level
+            .spawns
+            .get(self.next % level.spawns.len().max(1))
+            .copied()
+            .unwrap_or_default();
+        self.next += 1;
+
+        spawn
+    }
+}
+
+impl TerrainPiece {
+    pub(super) fn bundle(&self) -> impl Bundle {
+        (
+            Terrain {
+                shape: self.shape.clone(),
+            },
+            Transform::from_translation(self.position.extend(0.0)),
+            RigidBody::Kinematic,
+            self.shape.collider(),
+        )
+    }
+}
+
+impl PlatformPiece {
+    pub(super) fn bundle(&self) -> impl Bundle {
+        (
+            Terrain {
+                shape: self.shape.clone(),
+            },
+            Transform::from_translation(self.platform.start.extend(0.0)),
+            RigidBody::Kinematic,
+            self.shape.collider(),
+            self.platform.clone(),
+        )
+    }
+}
+
+impl TerrainShape {
+    /// Fails for polygons that [`TerrainShape::collider`] cannot build.
+    pub fn validate(&self) -> Result<()> {
+        if let TerrainShape::Polygon { vertices } = self {
+            if vertices.len() < 3 {
+                bail!("polygon has {} vertices, needs at least 3", vertices.len());
+            }
+
+            if Collider::convex_hull(vertices.clone()).is_none() {
+                bail!("polygon vertices {:?} are degenerate", vertices);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn collider(&self) -> Collider {
+        match self {
+            TerrainShape::Rectangle { width, height } => Collider::rectangle(*width, *height),
+            TerrainShape::Polygon { vertices } => Collider::convex_hull(vertices.clone())
+                .expect("terrain polygon should have at least three vertices"),
+        }
+    }
+
+    pub fn mesh(&self) -> Mesh {
+        match self {
+            TerrainShape::Rectangle { width, height } => Rectangle::new(*width, *height).into(),
+            TerrainShape::Polygon { vertices } => polygon_mesh(vertices),
+        }
+    }
+}
+
+/// Triangle fan of a convex polygon. The vertices are sorted counterclockwise
+/// around their center first, so they can be given in any order.
+fn polygon_mesh(vertices: &[Vec2]) -> Mesh {
+    let center = vertices.iter().sum::<Vec2>() / vertices.len().max(1) as f32;
+
+    let mut vertices = vertices.to_vec();
+    vertices.sort_by(|a, b| {
+        let a = (*a - center).to_angle();
+        let b = (*b - center).to_angle();
+        a.total_cmp(&b)
+    });
+
+    let positions = vertices.iter().map(|x| [x.x, x.y, 0.0]).collect::<Vec<_>>();
+    let indices = (1..vertices.len().saturating_sub(1) as u32)
+        .flat_map(|x| [0, x, x + 1])
+        .collect::<Vec<_>>();
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 0.0, 1.0]; vertices.len()],
+    )
+    .with_inserted_indices(Indices::U32(indices))
+}
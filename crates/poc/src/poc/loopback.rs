@@ -0,0 +1,176 @@
+use bevy::{asset::AssetPlugin, input::InputPlugin, prelude::*, time::TimeUpdateStrategy};
+use bevy_replicon::{
+    prelude::{ConnectedClient, RepliconClient, RepliconClientStatus, RepliconServer},
+    shared::backend::connected_client::NetworkId,
+};
+
+use super::{config::NetworkConfig, PocPlugin, PocType, Transport};
+
+/// Maximum message size reported to replicon for loopback clients.
+const MAX_MESSAGE_SIZE: usize = 1200;
+
+/// Creates an app without window or renderer that uses the loopback transport.
+/// The network conditioner from `network` is applied as usual, based on the
+/// manually advanced virtual time.
+pub fn headless_app(typ: PocType, network: NetworkConfig) -> App {
+    let mut app = App::new();
+
+    app.add_plugins((
+        MinimalPlugins,
+        TransformPlugin,
+        AssetPlugin::default(),
+        InputPlugin,
+    ))
+    .init_asset::<Mesh>()
+    .init_asset::<ColorMaterial>()
+    .add_plugins(PocPlugin {
+        typ,
+        transport: Transport::Loopback,
+        network,
+    });
+
+    app
+}
+
+pub struct LoopbackClient {
+    pub app: App,
+    /// Entity representing this client on the server.
+    pub client_entity: Entity,
+}
+
+/// Connects a server app and any number of client apps in the same process.
+/// Every call to [`LoopbackNetwork::step`] advances all apps by exactly one
+/// fixed tick and moves the messages they sent to their peers.
+pub struct LoopbackNetwork {
+    pub server: App,
+    pub clients: Vec<LoopbackClient>,
+    /// Total bytes sent from the server to clients.
+    pub server_bytes: usize,
+    /// Total bytes sent from clients to the server.
+    pub client_bytes: usize,
+}
+
+impl LoopbackNetwork {
+    pub fn new(mut server: App) -> Self {
+        prepare(&mut server);
+
+        server
+            .world_mut()
+            .resource_mut::<RepliconServer>()
+            .set_running(true);
+
+        Self {
+            server,
+            clients: Vec::new(),
+            server_bytes: 0,
+            client_bytes: 0,
+        }
+    }
+
+    /// Connects the client and returns its index in [`LoopbackNetwork::clients`].
+    pub fn connect(&mut self, mut app: App) -> usize {
+        prepare(&mut app);
+
+        let index = self.clients.len();
+
+        let client_entity = self
+            .server
+            .world_mut()
+            .spawn((
+                ConnectedClient {
+                    max_size: MAX_MESSAGE_SIZE,
+                },
+                NetworkId::new(index as u64),
+            ))
+            .id();
+
+        app.world_mut()
+            .resource_mut::<RepliconClient>()
+            .set_status(RepliconClientStatus::Connected);
+
+        self.clients.push(LoopbackClient { app, client_entity });
+
+        index
+    }
+
+    pub fn disconnect(&mut self, index: usize) {
+        let client = &mut self.clients[index];
+
+        client
+            .app
+            .world_mut()
+            .resource_mut::<RepliconClient>()
+            .set_status(RepliconClientStatus::Disconnected);
+
+        self.server.world_mut().despawn(client.client_entity);
+    }
+
+    pub fn step(&mut self) {
+        for client in &mut self.clients {
+            client.app.update();
+        }
+
+        self.transfer_to_server();
+        self.server.update();
+        self.transfer_to_clients();
+    }
+
+    pub fn run(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    fn transfer_to_server(&mut self) {
+        for client in &mut self.clients {
+            let mut replicon_client = client.app.world_mut().resource_mut::<RepliconClient>();
+
+            if !replicon_client.is_connected() {
+                continue;
+            }
+
+            let messages = replicon_client.drain_sent().collect::<Vec<_>>();
+            let mut replicon_server = self.server.world_mut().resource_mut::<RepliconServer>();
+
+            for (channel_id, message) in messages {
+                self.client_bytes += message.len();
+                replicon_server.insert_received(client.client_entity, channel_id, message);
+            }
+        }
+    }
+
+    fn transfer_to_clients(&mut self) {
+        let messages = self
+            .server
+            .world_mut()
+            .resource_mut::<RepliconServer>()
+            .drain_sent()
+            .collect::<Vec<_>>();
+
+        for (client_entity, channel_id, message) in messages {
+            let Some(client) = self
+                .clients
+                .iter_mut()
+                .find(|x| x.client_entity == client_entity)
+            else {
+                continue;
+            };
+
+            self.server_bytes += message.len();
+            client
+                .app
+                .world_mut()
+                .resource_mut::<RepliconClient>()
+                .insert_received(channel_id, message);
+        }
+    }
+}
+
+/// Finishes plugin setup and makes every update advance exactly one fixed tick.
+fn prepare(app: &mut App) {
+    app.finish();
+    app.cleanup();
+
+    let timestep = app.world().resource::<Time<Fixed>>().timestep();
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(timestep));
+}
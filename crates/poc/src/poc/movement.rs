@@ -1,12 +1,16 @@
 use avian2d::{math::*, prelude::*};
-use bevy::{ecs::query::Has, prelude::*};
+use bevy::prelude::*;
 use bevy_replicon::{
     prelude::{server_running, Channel, ClientEventAppExt, ClientTriggerAppExt, ServerEventAppExt},
     shared::replicon_tick::RepliconTick,
 };
 use serde::{Deserialize, Serialize};
 
-use super::Simulate;
+use super::{
+    algebraic::Algebraic,
+    platform::{move_platforms, MovingPlatform},
+    Simulate,
+};
 
 pub struct MovementPlugin;
 
@@ -15,7 +19,16 @@ impl Plugin for MovementPlugin {
         app.add_event::<MovementInput>()
             .add_client_event::<MovementInput>(Channel::Unreliable)
             .add_systems(Simulate, (movement, apply_movement_damping))
-            .add_systems(Simulate, update_grounded.after(PhysicsSet::Sync));
+            .add_systems(
+                Simulate,
+                carry_on_platforms
+                    .after(move_platforms)
+                    .before(PhysicsSet::StepSimulation),
+            )
+            .add_systems(
+                Simulate,
+                (update_grounded, update_wall_contact).after(PhysicsSet::Sync),
+            );
     }
 }
 
@@ -32,27 +45,161 @@ pub struct Movement {
     pub uses: u32,
 }
 
-#[derive(Component, Serialize, Deserialize, Clone)]
+#[derive(Component, Serialize, Deserialize, Clone, Debug)]
 #[component(storage = "SparseSet")]
-pub struct Grounded(bool);
+pub struct Grounded(pub(super) bool);
+
+impl Grounded {
+    pub fn get(&self) -> bool {
+        self.0
+    }
+}
+
+/// Booleans form a group under exclusive or, where every value is its own inverse.
+impl Algebraic for Grounded {
+    fn identity() -> Self {
+        Grounded(false)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Grounded(self.0 ^ other.0)
+    }
+
+    fn inverse(&self) -> Self {
+        self.clone()
+    }
+
+    fn distance(&self, other: &Self) -> f32 {
+        if self.0 == other.0 {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    fn magnitude(&self) -> f32 {
+        0.0
+    }
+}
+
+#[cfg(any(test, debug_assertions, feature = "bench"))]
+impl super::algebraic::RandomAlgebraic for Grounded {
+    fn random(rng: &mut impl rand::Rng) -> Self {
+        Grounded(rng.random())
+    }
+}
+
+/// Minimum horizontal part of a surface normal for the surface to count as a wall.
+const WALL_NORMAL_X: Scalar = 0.9;
 
+/// Side of the character that touches a wall.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum WallContact {
+    #[default]
+    None,
+    Left,
+    Right,
+}
+
+impl WallContact {
+    /// Direction from the character towards the wall.
+    pub fn direction(&self) -> Scalar {
+        match self {
+            WallContact::None => 0.0,
+            WallContact::Left => -1.0,
+            WallContact::Right => 1.0,
+        }
+    }
+}
+
+/// Casts a slightly smaller copy of the collider to both sides to find walls.
 #[derive(Component)]
+pub struct WallCaster {
+    shape: Collider,
+    max_distance: Scalar,
+}
+
+/// Timers and counters of the jump, predicted like the physics state.
+#[derive(Component, Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct JumpState {
+    /// Seconds left in which a jump is allowed after leaving the ground.
+    pub coyote: f32,
+    /// Seconds left in which a jump pressed too early still happens on landing.
+    pub buffer: f32,
+    pub air_jumps: u32,
+    /// Whether jump was held in the previous tick, a jump needs a new press.
+    pub held: bool,
+    /// Rising from a jump, releasing jump cuts it short.
+    pub rising: bool,
+}
+
+/// Tuning of a [`MovementController`]. Replicated from the server, clients
+/// never choose their own values.
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct MovementConfig {
     pub acceleration: f32,
+    /// Decay rate per second of the horizontal velocity while grounded, on top
+    /// of `air_damping`.
     pub damping: f32,
+    /// Decay rate per second of the horizontal velocity that always applies.
+    pub air_damping: f32,
     pub jump_impulse: f32,
+    /// Seconds after leaving the ground in which jumping is still allowed.
+    pub coyote_time: f32,
+    /// Seconds a jump pressed in the air is remembered until landing.
+    pub jump_buffer_time: f32,
+    /// Factor applied to the upward velocity when jump is released while rising.
+    pub jump_cut: f32,
+    /// Jumps allowed in the air before landing again.
+    pub air_jumps: u32,
+    /// Decay rate per second of the falling velocity while touching a wall.
+    pub wall_friction: f32,
+    /// Upward velocity of a jump off a wall.
+    pub wall_jump_impulse: f32,
+    /// Horizontal velocity away from the wall of a jump off a wall.
+    pub wall_jump_push: f32,
     pub max_slope_angle: Option<f32>,
     pub max_velocity: f32,
 }
 
+impl Default for MovementConfig {
+    fn default() -> Self {
+        Self {
+            acceleration: 2250.0,
+            damping: 2.5,
+            air_damping: 0.15,
+            jump_impulse: 300.0,
+            coyote_time: 0.1,
+            jump_buffer_time: 0.1,
+            jump_cut: 0.5,
+            air_jumps: 1,
+            wall_friction: 8.0,
+            wall_jump_impulse: 300.0,
+            wall_jump_push: 200.0,
+            max_slope_angle: Some(30.0f32.to_radians()),
+            max_velocity: 250.0,
+        }
+    }
+}
+
+/// Config the server gives to newly spawned players.
+#[derive(Resource, Clone, Default)]
+pub struct ServerMovementConfig(pub MovementConfig);
+
 #[derive(Bundle)]
 pub struct MovementController {
     body: RigidBody,
     collider: Collider,
     friction: Friction,
     ground_caster: ShapeCaster,
+    wall_caster: WallCaster,
     locked_axes: LockedAxes,
+    /// Whether a body sleeps depends on how long it rested, which a rollback
+    /// starting from a restored state does not know.
+    sleeping_disabled: SleepingDisabled,
     grounded: Grounded,
+    wall_contact: WallContact,
+    jump_state: JumpState,
 
     movement_config: MovementConfig,
     movement: Movement,
@@ -71,10 +218,17 @@ impl MovementController {
                 dynamic_coefficient: 0.0,
                 static_coefficient: 0.0,
             },
-            ground_caster: ShapeCaster::new(caster_shape, Vector::ZERO, 0.0, Dir2::NEG_Y)
+            ground_caster: ShapeCaster::new(caster_shape.clone(), Vector::ZERO, 0.0, Dir2::NEG_Y)
                 .with_max_distance(10.0),
+            wall_caster: WallCaster {
+                shape: caster_shape,
+                max_distance: 2.0,
+            },
             locked_axes: LockedAxes::ROTATION_LOCKED,
+            sleeping_disabled: SleepingDisabled,
             grounded: Grounded(false),
+            wall_contact: WallContact::None,
+            jump_state: JumpState::default(),
 
             movement_config,
             movement: Movement::default(),
@@ -109,6 +263,59 @@ fn update_grounded(
     }
 }
 
+fn update_wall_contact(
+    spatial_query: SpatialQuery,
+    mut query: Query<(Entity, &WallCaster, &Position, &Rotation, &mut WallContact), With<Movement>>,
+) {
+    for (entity, caster, position, rotation, mut wall_contact) in &mut query {
+        let config = ShapeCastConfig::from_max_distance(caster.max_distance);
+        let filter = SpatialQueryFilter::from_excluded_entities([entity]);
+
+        let touches = |direction: Dir2| {
+            spatial_query
+                .cast_shape(
+                    &caster.shape,
+                    position.0,
+                    rotation.as_radians(),
+                    direction,
+                    &config,
+                    &filter,
+                )
+                .is_some_and(|hit| hit.normal1.x.abs() >= WALL_NORMAL_X)
+        };
+
+        let contact = if touches(Dir2::NEG_X) {
+            WallContact::Left
+        } else if touches(Dir2::X) {
+            WallContact::Right
+        } else {
+            WallContact::None
+        };
+
+        wall_contact.set_if_neq(contact);
+    }
+}
+
+/// Moves grounded characters along with the platform below them, which they
+/// have no friction with.
+fn carry_on_platforms(
+    time: Res<Time>,
+    platforms: Query<&LinearVelocity, With<MovingPlatform>>,
+    mut riders: Query<(&ShapeHits, &Grounded, &mut Position), With<Movement>>,
+) {
+    let delta_time = time.delta_secs();
+
+    for (hits, grounded, mut position) in &mut riders {
+        if !grounded.0 {
+            continue;
+        }
+
+        if let Some(velocity) = hits.iter().find_map(|hit| platforms.get(hit.entity).ok()) {
+            position.x += velocity.x * delta_time;
+        }
+    }
+}
+
 fn movement(
     time: Res<Time>,
     mut controllers: Query<(
@@ -117,20 +324,33 @@ fn movement(
         &MovementConfig,
         &mut LinearVelocity,
         &Grounded,
+        &WallContact,
+        &mut JumpState,
     )>,
 ) {
     let delta_time = time.delta_secs_f64().adjust_precision();
 
-    for (mut movement, transform, movement_config, mut linear_velocity, grounded) in
-        &mut controllers
+    for (
+        mut movement,
+        transform,
+        movement_config,
+        mut linear_velocity,
+        grounded,
+        wall_contact,
+        mut jump,
+    ) in &mut controllers
     {
         let Some(input) = &movement.input else {
             continue;
         };
 
-        println!(
+        trace!(
             "Step jump={} y={} yt={} dt={} floor={}",
-            input.jump, transform.translation.y, linear_velocity.y, delta_time, grounded.0
+            input.jump,
+            transform.translation.y,
+            linear_velocity.y,
+            delta_time,
+            grounded.0
         );
 
         let movement_factor = if grounded.0 { 1.0 } else { 0.5 };
@@ -144,10 +364,45 @@ fn movement(
             .x
             .clamp(-movement_config.max_velocity, movement_config.max_velocity);
 
-        if grounded.0 && input.jump {
+        let pressed = input.jump && !jump.held;
+        jump.held = input.jump;
+
+        if grounded.0 {
+            jump.coyote = movement_config.coyote_time;
+            jump.air_jumps = movement_config.air_jumps;
+        }
+
+        if pressed {
+            jump.buffer = movement_config.jump_buffer_time;
+        }
+
+        if jump.buffer > 0.0 && jump.coyote > 0.0 {
+            linear_velocity.y = movement_config.jump_impulse;
+            jump.buffer = 0.0;
+            jump.coyote = 0.0;
+            jump.rising = true;
+        } else if pressed && *wall_contact != WallContact::None {
+            linear_velocity.y = movement_config.wall_jump_impulse;
+            linear_velocity.x = -wall_contact.direction() * movement_config.wall_jump_push;
+            jump.buffer = 0.0;
+            jump.rising = true;
+        } else if pressed && jump.air_jumps > 0 {
             linear_velocity.y = movement_config.jump_impulse;
+            jump.buffer = 0.0;
+            jump.air_jumps -= 1;
+            jump.rising = true;
+        } else if jump.rising && !input.jump {
+            linear_velocity.y *= movement_config.jump_cut;
+            jump.rising = false;
         }
 
+        if linear_velocity.y <= 0.0 {
+            jump.rising = false;
+        }
+
+        jump.coyote = (jump.coyote - delta_time).max(0.0);
+        jump.buffer = (jump.buffer - delta_time).max(0.0);
+
         movement.uses += 1;
 
         if movement.uses > 3 {
@@ -157,12 +412,32 @@ fn movement(
 }
 
 /// Slows down movement in the X direction.
-fn apply_movement_damping(mut query: Query<(Has<Grounded>, &MovementConfig, &mut LinearVelocity)>) {
-    for (is_grounded, movement_config, mut linear_velocity) in &mut query {
-        linear_velocity.x *= 0.995;
+fn apply_movement_damping(
+    time: Res<Time>,
+    mut query: Query<
+        (
+            &Grounded,
+            &WallContact,
+            &MovementConfig,
+            &mut LinearVelocity,
+        ),
+        With<Movement>,
+    >,
+) {
+    let delta_time = time.delta_secs();
 
-        if is_grounded {
-            linear_velocity.x *= movement_config.damping;
-        };
+    for (grounded, wall_contact, movement_config, mut linear_velocity) in &mut query {
+        let mut rate = movement_config.air_damping;
+
+        if grounded.0 {
+            rate += movement_config.damping;
+        }
+
+        linear_velocity.x *= ops::exp(-rate * delta_time);
+
+        // Slides down walls instead of falling.
+        if !grounded.0 && *wall_contact != WallContact::None && linear_velocity.y < 0.0 {
+            linear_velocity.y *= ops::exp(-movement_config.wall_friction * delta_time);
+        }
     }
 }
@@ -0,0 +1,66 @@
+use avian2d::prelude::{LinearVelocity, PhysicsSet, Position};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::Simulate;
+
+/// Server tick that [`Simulate`] steps to. Clients run ahead of the server and
+/// use the server tick at which their input will be simulated.
+#[derive(Resource, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct SimulationTick(pub u32);
+
+/// Kinematic body moving back and forth between `start` and `end`. Its position
+/// only depends on [`SimulationTick`], so clients can evaluate it at any
+/// predicted tick instead of waiting for the server.
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MovingPlatform {
+    pub start: Vec2,
+    pub end: Vec2,
+    /// Ticks to move from `start` to `end`.
+    pub period: u32,
+}
+
+impl MovingPlatform {
+    pub fn position(&self, tick: u32) -> Vec2 {
+        let period = self.period.max(1);
+        let phase = (tick % (2 * period)) as f32 / period as f32;
+        let t = if phase <= 1.0 { phase } else { 2.0 - phase };
+
+        self.start.lerp(self.end, t)
+    }
+}
+
+pub struct PlatformPlugin;
+
+impl Plugin for PlatformPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationTick>().add_systems(
+            Simulate,
+            move_platforms
+                .after(PhysicsSet::Prepare)
+                .before(PhysicsSet::StepSimulation),
+        );
+    }
+}
+
+/// Places platforms at their position of the previous tick with the velocity
+/// that takes them to the position of the current tick.
+pub(super) fn move_platforms(
+    time: Res<Time>,
+    tick: Res<SimulationTick>,
+    mut query: Query<(&MovingPlatform, &mut Position, &mut LinearVelocity)>,
+) {
+    let delta_time = time.delta_secs();
+
+    if delta_time == 0.0 {
+        return;
+    }
+
+    for (platform, mut position, mut linear_velocity) in &mut query {
+        let from = platform.position(tick.0.wrapping_sub(1));
+        let to = platform.position(tick.0);
+
+        position.0 = from;
+        linear_velocity.0 = (to - from) / delta_time;
+    }
+}
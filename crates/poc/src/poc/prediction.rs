@@ -0,0 +1,229 @@
+use std::{mem, time::Duration};
+
+use avian2d::prelude::{AngularVelocity, Collider, Gravity, LinearVelocity, RigidBody};
+use bevy::{
+    ecs::entity::{EntityHashMap, EntityHashSet},
+    prelude::*,
+};
+
+use super::{
+    configure_simulation,
+    movement::{Grounded, JumpState, Movement, MovementConfig, MovementController, WallContact},
+    platform::MovingPlatform,
+    remote::RemotePoses,
+    replay_inputs, simulation_app, MovementInput, Predicted, SimulationMode,
+};
+
+/// Separate world in which rollbacks replay inputs. It only contains copies of
+/// the predicted entities and of the static and kinematic colliders they can
+/// touch, so replays neither step anything else nor touch the `Time` of the
+/// client world.
+#[derive(Resource)]
+pub struct PredictionWorld {
+    world: World,
+    /// Client world entity to its copy in the prediction world.
+    entities: EntityHashMap<Entity>,
+}
+
+impl PredictionWorld {
+    pub fn new(gravity: Vec2, mode: SimulationMode) -> Self {
+        let mut app = simulation_app(gravity);
+        configure_simulation(app.world_mut(), mode);
+
+        Self {
+            world: mem::take(app.world_mut()),
+            entities: EntityHashMap::default(),
+        }
+    }
+
+    /// Copies the current state into the prediction world, replays `inputs` for
+    /// `entities` and copies the predicted state back. See [`replay_inputs`] for
+    /// `tick_offset`.
+    pub fn replay<'a>(
+        &mut self,
+        main: &mut World,
+        entities: &[Entity],
+        inputs: impl IntoIterator<Item = &'a MovementInput>,
+        dt: Duration,
+        tick_offset: u32,
+    ) {
+        self.sync_from(main);
+
+        let copies = entities
+            .iter()
+            .filter_map(|entity| self.entities.get(entity).copied())
+            .collect::<Vec<_>>();
+
+        replay_inputs(&mut self.world, &copies, inputs, dt, tick_offset);
+
+        self.sync_to(main);
+    }
+
+    fn sync_from(&mut self, main: &mut World) {
+        let mut seen = EntityHashSet::default();
+
+        let mut query_predicted = main.query_filtered::<(
+            Entity,
+            &Collider,
+            &MovementConfig,
+            &Movement,
+            &Transform,
+            &LinearVelocity,
+            &AngularVelocity,
+            &Grounded,
+            &WallContact,
+            &JumpState,
+        ), With<Predicted>>();
+
+        for (
+            entity,
+            collider,
+            config,
+            movement,
+            transform,
+            linear_velocity,
+            angular_velocity,
+            grounded,
+            wall_contact,
+            jump,
+        ) in query_predicted.iter(main)
+        {
+            seen.insert(entity);
+
+            let copy = *self.entities.entry(entity).or_insert_with(|| {
+                self.world
+                    .spawn(MovementController::new(collider.clone(), config.clone()))
+                    .id()
+            });
+
+            let mut copy = self.world.entity_mut(copy);
+
+            // The server can change the tuning during the session.
+            if copy.get::<MovementConfig>() != Some(config) {
+                copy.insert(config.clone());
+            }
+
+            copy.insert((
+                *transform,
+                *linear_velocity,
+                *angular_velocity,
+                grounded.clone(),
+                *wall_contact,
+                jump.clone(),
+                Movement {
+                    input: movement.input.clone(),
+                    uses: movement.uses,
+                },
+            ));
+        }
+
+        let mut query_colliders = main.query_filtered::<(
+            Entity,
+            &Collider,
+            &RigidBody,
+            &Transform,
+            Option<&MovingPlatform>,
+            Option<&RemotePoses>,
+        ), Without<Predicted>>();
+
+        for (entity, collider, body, transform, platform, poses) in query_colliders.iter(main) {
+            if body.is_dynamic() {
+                continue;
+            }
+
+            seen.insert(entity);
+
+            match self.entities.get(&entity) {
+                Some(&copy) => {
+                    let mut copy = self.world.entity_mut(copy);
+
+                    if copy.get::<Transform>() != Some(transform) {
+                        copy.insert(*transform);
+                    }
+
+                    // Remote bodies are moved through the replayed ticks.
+                    if let Some(poses) = poses {
+                        copy.insert(poses.clone());
+                    }
+                }
+                None => {
+                    let mut copy = self.world.spawn((*transform, *body, collider.clone()));
+
+                    // Platforms are moved by the replay itself.
+                    if let Some(platform) = platform {
+                        copy.insert(platform.clone());
+                    }
+
+                    if let Some(poses) = poses {
+                        copy.insert(poses.clone());
+                    }
+
+                    self.entities.insert(entity, copy.id());
+                }
+            }
+        }
+
+        self.entities.retain(|entity, copy| {
+            let keep = seen.contains(entity);
+
+            if !keep {
+                self.world.despawn(*copy);
+            }
+
+            keep
+        });
+    }
+
+    fn sync_to(&mut self, main: &mut World) {
+        let mut query = self.world.query::<(
+            &Transform,
+            &LinearVelocity,
+            &AngularVelocity,
+            &Grounded,
+            &WallContact,
+            &JumpState,
+            &Movement,
+        )>();
+
+        for (&entity, &copy) in &self.entities {
+            // Colliders that are not predicted have no movement.
+            let Ok((
+                transform,
+                linear_velocity,
+                angular_velocity,
+                grounded,
+                wall_contact,
+                jump,
+                movement,
+            )) = query.get(&self.world, copy)
+            else {
+                continue;
+            };
+
+            let Ok(mut entity) = main.get_entity_mut(entity) else {
+                continue;
+            };
+
+            entity.insert((
+                *transform,
+                *linear_velocity,
+                *angular_velocity,
+                grounded.clone(),
+                *wall_contact,
+                jump.clone(),
+                Movement {
+                    input: movement.input.clone(),
+                    uses: movement.uses,
+                },
+            ));
+        }
+    }
+}
+
+pub(super) fn system_init_prediction_world(
+    mut commands: Commands,
+    gravity: Res<Gravity>,
+    mode: Res<SimulationMode>,
+) {
+    commands.insert_resource(PredictionWorld::new(gravity.0, *mode));
+}
@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use bevy_replicon::prelude::RepliconServer;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    lag_compensation::ColliderHistory,
+    platform::SimulationTick,
+    spawn::{LocalSpawn, SpawnAppExt, Spawnable},
+    Simulate,
+};
+
+/// Projectiles further away from the player who fired them are rejected.
+const MAX_SPAWN_DISTANCE: f32 = 60.0;
+
+/// Projectiles fired further from the current server tick are rejected.
+const MAX_SPAWN_TICKS: u32 = 30;
+
+const MAX_SPEED: f32 = 1000.0;
+
+pub const PROJECTILE_SPEED: f32 = 600.0;
+
+/// Ticks until projectiles disappear.
+pub const PROJECTILE_LIFETIME: u32 = 60;
+
+/// Moves in a straight line from `origin`, fired during the server tick
+/// `tick`. Its position only depends on [`SimulationTick`], so the local and
+/// the server projectile are at the same place.
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[require(Transform)]
+pub struct Projectile {
+    pub origin: Vec2,
+    pub velocity: Vec2,
+    pub tick: u32,
+}
+
+impl Projectile {
+    /// Position at `tick`, `dt` is the length of a tick. Requests can reach the
+    /// server before `self.tick`, the projectile waits at `origin` until then.
+    pub fn position(&self, tick: u32, dt: f32) -> Vec2 {
+        self.origin + self.velocity * tick.saturating_sub(self.tick) as f32 * dt
+    }
+
+    pub fn expired(&self, tick: u32) -> bool {
+        tick.saturating_sub(self.tick) > PROJECTILE_LIFETIME
+    }
+}
+
+impl Spawnable for Projectile {
+    /// Checks the origin against the pose of the player at `self.tick`, where the
+    /// client predicted it. That is the newest pose if the server did not reach
+    /// `self.tick` yet, projectiles fired before the rewind window are rejected.
+    fn accept(&self, player: &ColliderHistory, server_tick: u32) -> bool {
+        let Some(pose) = player.at(self.tick) else {
+            return false;
+        };
+
+        self.origin.distance(pose.transform.translation.truncate()) <= MAX_SPAWN_DISTANCE
+            && self.tick.abs_diff(server_tick) <= MAX_SPAWN_TICKS
+            && self.velocity.length() <= MAX_SPEED
+    }
+}
+
+pub(super) struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_predicted_spawn::<Projectile>()
+            .add_systems(Simulate, move_projectiles);
+    }
+}
+
+fn move_projectiles(
+    mut commands: Commands,
+    time: Res<Time>,
+    tick: Res<SimulationTick>,
+    server: Option<Res<RepliconServer>>,
+    query: Query<(Entity, &Projectile, &mut Transform, Has<LocalSpawn>)>,
+) {
+    let delta_time = time.delta_secs();
+    let server = server.is_some_and(|x| x.is_running());
+
+    for (entity, projectile, mut transform, local) in query {
+        if projectile.expired(tick.0) {
+            // Clients receive the despawn of server projectiles.
+            if server || local {
+                commands.entity(entity).despawn();
+            }
+            continue;
+        }
+
+        transform.translation = projectile
+            .position(tick.0, delta_time)
+            .extend(transform.translation.z);
+    }
+}
@@ -0,0 +1,501 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+use avian2d::prelude::{AngularVelocity, Collider, Gravity, LinearVelocity, RigidBody};
+use bevy::prelude::*;
+use bevy_replicon::server::server_tick::ServerTick;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    configure_simulation, fixed_hz,
+    movement::{Grounded, JumpState, Movement, MovementConfig, MovementController, WallContact},
+    platform::{MovingPlatform, SimulationTick},
+    simulation_app, tick_duration, MovementInput, Player, Simulate, SimulationMode, Terrain,
+};
+
+/// Upper bound for the length of a frame, so a corrupt length prefix cannot
+/// allocate arbitrary amounts of memory.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// One entry of a session recording.
+#[derive(Serialize, Deserialize)]
+pub enum RecordEntry {
+    /// First entry of every recording, describes the static world.
+    Header {
+        hz: f32,
+        gravity: Vec2,
+        /// Solver settings of the server, replays use the same ones.
+        simulation: SimulationMode,
+        terrain: Vec<TerrainSnapshot>,
+    },
+    /// Inputs received before simulating `tick`.
+    Tick {
+        tick: u32,
+        inputs: Vec<RecordedInput>,
+    },
+    /// Movement config of a player changed before simulating `tick`, e.g.
+    /// because the server reloaded its presets.
+    Config {
+        tick: u32,
+        player_id: u64,
+        config: MovementConfig,
+    },
+    /// Player teleported to `position` after simulating `tick`.
+    Respawn {
+        tick: u32,
+        player_id: u64,
+        position: Vec2,
+    },
+    /// State of all players after simulating `tick`.
+    Snapshot {
+        tick: u32,
+        players: Vec<PlayerSnapshot>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TerrainSnapshot {
+    pub transform: Transform,
+    pub body: RigidBody,
+    pub collider: Collider,
+    pub platform: Option<MovingPlatform>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordedInput {
+    pub player_id: u64,
+    pub input: MovementInput,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub player_id: u64,
+    pub transform: Transform,
+    pub linear_velocity: LinearVelocity,
+    pub angular_velocity: AngularVelocity,
+    pub grounded: Grounded,
+    pub wall_contact: WallContact,
+    pub jump: JumpState,
+    pub input: Option<MovementInput>,
+    pub uses: u32,
+    pub collider: Collider,
+    pub config: MovementConfig,
+}
+
+/// Writes a value as a postcard frame prefixed with its length.
+pub fn write_frame<T: Serialize>(writer: &mut impl Write, value: &T) -> Result<()> {
+    let bytes = postcard::to_stdvec(value)?;
+
+    if bytes.len() > MAX_FRAME_SIZE {
+        bail!(
+            "frame of {} bytes exceeds {} bytes",
+            bytes.len(),
+            MAX_FRAME_SIZE
+        );
+    }
+
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)?;
+
+    Ok(())
+}
+
+/// Reads all frames written by [`write_frame`]. A truncated last frame, e.g. from a
+/// process that was killed while writing, is ignored.
+pub fn read_frames<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut frames = Vec::new();
+    let mut length = [0; 4];
+
+    loop {
+        match reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+
+        let size = u32::from_le_bytes(length) as usize;
+
+        if size > MAX_FRAME_SIZE {
+            bail!(
+                "{}: frame of {} bytes exceeds {} bytes",
+                path.display(),
+                size,
+                MAX_FRAME_SIZE
+            );
+        }
+
+        let mut bytes = vec![0; size];
+
+        match reader.read_exact(&mut bytes) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+
+        frames.push(postcard::from_bytes(&bytes)?);
+    }
+
+    Ok(frames)
+}
+
+/// Records the server session if inserted as a resource.
+#[derive(Resource)]
+pub struct Recorder {
+    writer: BufWriter<File>,
+    /// Ticks between two snapshots. Snapshots are also taken when players join.
+    snapshot_interval: u32,
+    players: Vec<u64>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, snapshot_interval: u32) -> Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            snapshot_interval: snapshot_interval.max(1),
+            players: Vec::new(),
+        })
+    }
+
+    pub(super) fn record_respawn(&mut self, tick: u32, player_id: u64, position: Vec2) {
+        self.write(&RecordEntry::Respawn {
+            tick,
+            player_id,
+            position,
+        });
+    }
+
+    fn write(&mut self, entry: &RecordEntry) {
+        if let Err(err) = write_frame(&mut self.writer, entry) {
+            println!("Failed to record: {}", err);
+        }
+    }
+}
+
+pub(super) fn system_record_header(
+    mut recorder: ResMut<Recorder>,
+    time: Res<Time<Fixed>>,
+    gravity: Res<Gravity>,
+    simulation: Res<SimulationMode>,
+    query_terrain: Query<
+        (&Transform, &RigidBody, &Collider, Option<&MovingPlatform>),
+        With<Terrain>,
+    >,
+) {
+    let terrain = query_terrain
+        .iter()
+        .map(|(transform, body, collider, platform)| TerrainSnapshot {
+            transform: *transform,
+            body: *body,
+            collider: collider.clone(),
+            platform: platform.cloned(),
+        })
+        .collect();
+
+    recorder.write(&RecordEntry::Header {
+        hz: fixed_hz(&time),
+        gravity: gravity.0,
+        simulation: *simulation,
+        terrain,
+    });
+}
+
+pub(super) fn system_record_tick(
+    mut recorder: ResMut<Recorder>,
+    server_tick: Res<ServerTick>,
+    query_players: Query<(&Player, &Movement, Ref<MovementConfig>)>,
+) {
+    let tick = server_tick.get();
+
+    // New players start with the config of their first snapshot.
+    for (player, _, config) in &query_players {
+        if config.is_changed() && !config.is_added() {
+            recorder.write(&RecordEntry::Config {
+                tick,
+                player_id: player.player_id,
+                config: config.clone(),
+            });
+        }
+    }
+
+    // Inputs received this tick were just applied and not used yet.
+    let inputs = query_players
+        .iter()
+        .filter(|(_, movement, _)| movement.uses == 0)
+        .filter_map(|(player, movement, _)| {
+            Some(RecordedInput {
+                player_id: player.player_id,
+                input: movement.input.clone()?,
+            })
+        })
+        .collect();
+
+    recorder.write(&RecordEntry::Tick { tick, inputs });
+}
+
+pub(super) fn system_record_snapshot(
+    mut recorder: ResMut<Recorder>,
+    server_tick: Res<ServerTick>,
+    query_players: Query<(
+        &Player,
+        &Transform,
+        &LinearVelocity,
+        &AngularVelocity,
+        &Grounded,
+        &WallContact,
+        &JumpState,
+        &Movement,
+        &Collider,
+        &MovementConfig,
+    )>,
+) {
+    let mut player_ids = query_players
+        .iter()
+        .map(|(player, ..)| player.player_id)
+        .collect::<Vec<_>>();
+    player_ids.sort();
+
+    let tick = server_tick.get();
+
+    if tick % recorder.snapshot_interval != 0 && player_ids == recorder.players {
+        return;
+    }
+
+    let players = query_players
+        .iter()
+        .map(
+            |(
+                player,
+                transform,
+                linear_velocity,
+                angular_velocity,
+                grounded,
+                wall_contact,
+                jump,
+                movement,
+                collider,
+                config,
+            )| {
+                PlayerSnapshot {
+                    player_id: player.player_id,
+                    transform: *transform,
+                    linear_velocity: *linear_velocity,
+                    angular_velocity: *angular_velocity,
+                    grounded: grounded.clone(),
+                    wall_contact: *wall_contact,
+                    jump: jump.clone(),
+                    input: movement.input.clone(),
+                    uses: movement.uses,
+                    collider: collider.clone(),
+                    config: config.clone(),
+                }
+            },
+        )
+        .collect();
+
+    recorder.players = player_ids;
+    recorder.write(&RecordEntry::Snapshot { tick, players });
+
+    if let Err(err) = recorder.writer.flush() {
+        println!("Failed to flush recording: {}", err);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub ticks: u32,
+    pub snapshots: u32,
+    pub max_position_error: f32,
+    pub max_velocity_error: f32,
+    /// First tick at which a recorded state differed by more than the tolerance.
+    pub first_divergence: Option<u32>,
+}
+
+/// State of a player after simulating a tick during a replay.
+pub struct ReplayedPlayer {
+    pub player_id: u64,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    /// The input tick matches the client tick that was predicted with this input.
+    pub input: Option<MovementInput>,
+}
+
+/// Re-runs the [`Simulate`] schedule headlessly with the recorded inputs and
+/// compares the result with every recorded snapshot. Players are spawned from
+/// the first snapshot they appear in.
+pub fn replay(path: &Path, tolerance: f32) -> Result<ReplayReport> {
+    replay_with(path, tolerance, |_, _| {})
+}
+
+/// Like [`replay`], but calls `on_tick` with the server tick and all players
+/// after simulating every tick.
+pub fn replay_with(
+    path: &Path,
+    tolerance: f32,
+    mut on_tick: impl FnMut(u32, &[ReplayedPlayer]),
+) -> Result<ReplayReport> {
+    let mut entries = read_frames::<RecordEntry>(path)?.into_iter();
+
+    let Some(RecordEntry::Header {
+        hz,
+        gravity,
+        simulation,
+        terrain,
+    }) = entries.next()
+    else {
+        bail!("{} does not start with a header", path.display());
+    };
+
+    let mut app = simulation_app(gravity);
+    let world = app.world_mut();
+    configure_simulation(world, simulation);
+
+    for terrain in terrain {
+        let mut entity = world.spawn((terrain.transform, terrain.body, terrain.collider));
+
+        if let Some(platform) = terrain.platform {
+            entity.insert(platform);
+        }
+    }
+
+    let dt = tick_duration(hz);
+    *world.resource_mut::<Time>() = Time::new_with(());
+
+    let mut players = HashMap::<u64, Entity>::new();
+    let mut report = ReplayReport::default();
+
+    for entry in entries {
+        match entry {
+            RecordEntry::Header { .. } => bail!("{} contains several headers", path.display()),
+            RecordEntry::Tick { tick, inputs } => {
+                let mut query_movement = world.query::<&mut Movement>();
+
+                for mut movement in query_movement.iter_mut(world) {
+                    if let Some(input) = &mut movement.input {
+                        input.tick += 1;
+                    }
+                }
+
+                for recorded in inputs {
+                    let Some(&entity) = players.get(&recorded.player_id) else {
+                        continue;
+                    };
+
+                    let mut movement = query_movement.get_mut(world, entity)?;
+                    movement.input = Some(recorded.input);
+                    movement.uses = 0;
+                }
+
+                world.insert_resource(SimulationTick(tick));
+                world.resource_mut::<Time>().advance_by(dt);
+                world.run_schedule(Simulate);
+                report.ticks += 1;
+
+                let mut query = world.query::<(&Transform, &LinearVelocity, &Movement)>();
+                let mut replayed = Vec::new();
+
+                for (&player_id, &entity) in &players {
+                    let (transform, linear_velocity, movement) = query.get(world, entity)?;
+
+                    replayed.push(ReplayedPlayer {
+                        player_id,
+                        position: transform.translation.truncate(),
+                        velocity: linear_velocity.0,
+                        input: movement.input.clone(),
+                    });
+                }
+
+                on_tick(tick, &replayed);
+            }
+            RecordEntry::Config {
+                player_id, config, ..
+            } => {
+                if let Some(&entity) = players.get(&player_id) {
+                    world.entity_mut(entity).insert(config);
+                }
+            }
+            RecordEntry::Respawn {
+                player_id,
+                position,
+                ..
+            } => {
+                let Some(&entity) = players.get(&player_id) else {
+                    continue;
+                };
+
+                world.entity_mut(entity).insert((
+                    Transform::from_translation(position.extend(0.0)),
+                    LinearVelocity::ZERO,
+                    AngularVelocity::ZERO,
+                    JumpState::default(),
+                    WallContact::None,
+                ));
+            }
+            RecordEntry::Snapshot {
+                tick,
+                players: snapshots,
+            } => {
+                report.snapshots += 1;
+
+                for snapshot in snapshots {
+                    let Some(&entity) = players.get(&snapshot.player_id) else {
+                        let entity = world
+                            .spawn((
+                                MovementController::new(snapshot.collider, snapshot.config),
+                                snapshot.transform,
+                                snapshot.linear_velocity,
+                                snapshot.angular_velocity,
+                            ))
+                            .insert((
+                                snapshot.grounded,
+                                snapshot.wall_contact,
+                                snapshot.jump,
+                                Movement {
+                                    input: snapshot.input,
+                                    uses: snapshot.uses,
+                                },
+                            ))
+                            .id();
+
+                        players.insert(snapshot.player_id, entity);
+                        continue;
+                    };
+
+                    let mut query = world.query::<(&Transform, &LinearVelocity)>();
+                    let (transform, linear_velocity) = query.get(world, entity)?;
+
+                    let position_error = transform
+                        .translation
+                        .distance(snapshot.transform.translation);
+                    let velocity_error = linear_velocity.0.distance(snapshot.linear_velocity.0);
+
+                    report.max_position_error = report.max_position_error.max(position_error);
+                    report.max_velocity_error = report.max_velocity_error.max(velocity_error);
+
+                    if (position_error > tolerance || velocity_error > tolerance)
+                        && report.first_divergence.is_none()
+                    {
+                        println!(
+                            "Tick {}: player {} diverged, position {} recorded {}, velocity {} recorded {}",
+                            tick,
+                            snapshot.player_id,
+                            transform.translation,
+                            snapshot.transform.translation,
+                            linear_velocity.0,
+                            snapshot.linear_velocity.0
+                        );
+
+                        report.first_divergence = Some(tick);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
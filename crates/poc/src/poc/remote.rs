@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+
+use avian2d::prelude::{LinearVelocity, PhysicsSet, Position};
+use bevy::prelude::*;
+
+use super::{platform::SimulationTick, ClientContext, IndividualServerConfig, Simulate};
+
+/// Server poses kept per remote body.
+const MAX_POSES: usize = 64;
+
+/// Remote bodies stop after moving this many ticks past their newest server pose.
+const MAX_EXTRAPOLATION_TICKS: u32 = 10;
+
+/// How the client moves the players of other clients.
+#[derive(Resource, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RemoteBodies {
+    /// Static colliders at the interpolated server transform. Their delay is
+    /// not the one hit detection rewinds by.
+    Static,
+    /// Kinematic colliders at the server pose a few ticks behind the predicted
+    /// tick, so they move smoothly but lag behind the predicted player. The
+    /// server announces the delay and rewinds its hit detection by the same
+    /// amount, which only matches this mode.
+    #[default]
+    Interpolated,
+    /// Kinematic colliders extrapolated from the newest server pose to the
+    /// predicted tick, so they are roughly where the server will have them.
+    Extrapolated,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RemotePose {
+    /// Server tick of the replication message.
+    pub tick: u32,
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+/// Server poses of a kinematic remote body, newest last. Replicated transforms
+/// and velocities are written here instead of to the components.
+#[derive(Component, Clone, Default, Debug)]
+pub struct RemotePoses {
+    pub poses: VecDeque<RemotePose>,
+    /// Ticks between the simulated tick and the pose the body is moved to.
+    pub lag: u32,
+}
+
+impl RemotePoses {
+    pub fn push_position(&mut self, tick: u32, position: Vec2) {
+        self.pose_at(tick).position = position;
+    }
+
+    pub fn push_velocity(&mut self, tick: u32, velocity: Vec2) {
+        self.pose_at(tick).velocity = velocity;
+    }
+
+    /// Pose received with the message of `tick`, components that did not change
+    /// in that message keep their previous values.
+    fn pose_at(&mut self, tick: u32) -> &mut RemotePose {
+        let previous = self.poses.back().copied();
+
+        if previous.is_none_or(|x| x.tick != tick) {
+            let mut pose = previous.unwrap_or(RemotePose {
+                tick,
+                position: Vec2::ZERO,
+                velocity: Vec2::ZERO,
+            });
+            pose.tick = tick;
+
+            self.poses.push_back(pose);
+
+            if self.poses.len() > MAX_POSES {
+                self.poses.pop_front();
+            }
+        }
+
+        self.poses.back_mut().unwrap()
+    }
+
+    /// Position at `tick`, interpolated between the server poses around it or
+    /// extrapolated from the newest one. `dt` is the length of a tick.
+    pub fn position(&self, tick: u32, dt: f32) -> Option<Vec2> {
+        let newest = self.poses.back()?;
+
+        if tick >= newest.tick {
+            let ticks = (tick - newest.tick).min(MAX_EXTRAPOLATION_TICKS);
+            return Some(newest.position + newest.velocity * ticks as f32 * dt);
+        }
+
+        let Some(index) = self.poses.iter().position(|x| x.tick > tick) else {
+            return Some(newest.position);
+        };
+
+        let Some(from) = index.checked_sub(1).map(|x| self.poses[x]) else {
+            return Some(self.poses[index].position);
+        };
+        let to = self.poses[index];
+
+        let t = (tick - from.tick) as f32 / (to.tick - from.tick) as f32;
+
+        Some(from.position.lerp(to.position, t))
+    }
+
+    /// Keeps only the newest pose, so the body does not move through the
+    /// poses before a teleport.
+    pub fn reset(&mut self) {
+        let newest = self.poses.pop_back();
+        self.poses.clear();
+        self.poses.extend(newest);
+    }
+}
+
+pub struct RemoteBodyPlugin;
+
+impl Plugin for RemoteBodyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Simulate,
+            move_remote_bodies
+                .after(PhysicsSet::Prepare)
+                .before(PhysicsSet::StepSimulation),
+        );
+    }
+}
+
+/// Places remote bodies at their pose of the previous tick with the velocity
+/// that takes them to the pose of the current tick, like moving platforms.
+pub(super) fn move_remote_bodies(
+    time: Res<Time>,
+    tick: Res<SimulationTick>,
+    mut query: Query<(&RemotePoses, &mut Position, &mut LinearVelocity)>,
+) {
+    let delta_time = time.delta_secs();
+
+    if delta_time == 0.0 {
+        return;
+    }
+
+    for (poses, mut position, mut linear_velocity) in &mut query {
+        let tick = tick.0.wrapping_sub(poses.lag);
+
+        let (Some(from), Some(to)) = (
+            poses.position(tick.wrapping_sub(1), delta_time),
+            poses.position(tick, delta_time),
+        ) else {
+            continue;
+        };
+
+        position.0 = from;
+        linear_velocity.0 = (to - from) / delta_time;
+    }
+}
+
+/// Moves interpolated bodies by the interpolation delay of the server into the
+/// past. Bodies are extrapolated if the latency exceeds the delay.
+pub(super) fn system_update_remote_lag(
+    mode: Res<RemoteBodies>,
+    client_context: Res<ClientContext>,
+    query_server_config: Query<&IndividualServerConfig>,
+    query: Query<&mut RemotePoses>,
+) {
+    let interpolation_ticks = client_context
+        .individual_config
+        .and_then(|entity| query_server_config.get(entity).ok())
+        .map(|config| config.interpolation_ticks)
+        .unwrap_or_default();
+
+    for mut poses in query {
+        let lag = match *mode {
+            RemoteBodies::Interpolated => interpolation_ticks,
+            _ => 0,
+        };
+
+        if poses.lag != lag {
+            poses.lag = lag;
+        }
+    }
+}
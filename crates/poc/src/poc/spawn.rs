@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_replicon::{
+    prelude::{
+        client_connected, server_running, AppRuleExt, Channel, ClientEventAppExt, ClientVisibility,
+        FromClient, Replicated, SendMode, ServerEventAppExt, ToClients,
+    },
+    server::server_tick::ServerTick,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{lag_compensation::ColliderHistory, ClientContext, ClientInfo};
+
+/// Replicated on entities the server spawned for a client request, so the
+/// requesting client can match them with the entity it spawned ahead.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PredictedSpawn {
+    pub player_id: u64,
+    pub id: u32,
+}
+
+/// Entity spawned by the client that waits for the server to confirm it.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct LocalSpawn {
+    pub id: u32,
+}
+
+#[derive(Event, Serialize, Deserialize, Clone, Debug)]
+pub struct SpawnRequest<T> {
+    pub id: u32,
+    pub value: T,
+}
+
+#[derive(Event, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct SpawnRejected {
+    pub id: u32,
+}
+
+/// Components that clients can spawn before the server confirmed them.
+pub trait Spawnable: Component + Clone + Serialize + DeserializeOwned {
+    /// Whether the server spawns `self` for the player with the recent poses
+    /// `player` during `server_tick`.
+    fn accept(&self, player: &ColliderHistory, server_tick: u32) -> bool;
+}
+
+/// Local entities of this client that were not confirmed or rejected yet.
+#[derive(Resource, Default)]
+pub struct LocalSpawns {
+    next_id: u32,
+    pending: HashMap<u32, Entity>,
+}
+
+impl LocalSpawns {
+    /// Spawns `value` locally and asks the server to spawn it as well. The local
+    /// entity is replaced by the server entity or despawned if the server
+    /// rejects it.
+    pub fn spawn<T: Spawnable>(
+        &mut self,
+        commands: &mut Commands,
+        writer: &mut EventWriter<SpawnRequest<T>>,
+        value: T,
+    ) -> Entity {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let entity = commands.spawn((value.clone(), LocalSpawn { id })).id();
+        self.pending.insert(id, entity);
+
+        writer.write(SpawnRequest { id, value });
+
+        entity
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+pub(super) struct SpawnPlugin;
+
+impl Plugin for SpawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.replicate::<PredictedSpawn>()
+            .add_server_event::<SpawnRejected>(Channel::Ordered)
+            .init_resource::<LocalSpawns>()
+            .add_observer(observer_client_confirm_spawn)
+            .add_systems(
+                PreUpdate,
+                system_client_reject_spawn.run_if(client_connected),
+            );
+    }
+}
+
+pub(super) trait SpawnAppExt {
+    /// Lets clients spawn `T` with [`LocalSpawns::spawn`].
+    fn add_predicted_spawn<T: Spawnable>(&mut self) -> &mut Self;
+}
+
+impl SpawnAppExt for App {
+    fn add_predicted_spawn<T: Spawnable>(&mut self) -> &mut Self {
+        self.add_client_event::<SpawnRequest<T>>(Channel::Ordered)
+            .add_systems(FixedUpdate, system_server_spawn::<T>.run_if(server_running))
+    }
+}
+
+fn system_server_spawn<T: Spawnable>(
+    mut reader: EventReader<FromClient<SpawnRequest<T>>>,
+    mut writer: EventWriter<ToClients<SpawnRejected>>,
+    mut commands: Commands,
+    server_tick: Res<ServerTick>,
+    query_client_info: Query<&ClientInfo>,
+    query_player: Query<&ColliderHistory>,
+    mut query_client_visibility: Query<&mut ClientVisibility>,
+) {
+    for request in reader.read() {
+        let Ok(client_info) = query_client_info.get(request.client_entity) else {
+            continue;
+        };
+
+        let accepted = query_player
+            .get(client_info.player)
+            .is_ok_and(|player| request.event.value.accept(player, server_tick.get()));
+
+        if !accepted {
+            println!(
+                "Rejected spawn {} of {}",
+                request.event.id, client_info.player_id
+            );
+
+            writer.write(ToClients {
+                mode: SendMode::Direct(request.client_entity),
+                event: SpawnRejected {
+                    id: request.event.id,
+                },
+            });
+            continue;
+        }
+
+        let entity = commands
+            .spawn((
+                request.event.value.clone(),
+                PredictedSpawn {
+                    player_id: client_info.player_id,
+                    id: request.event.id,
+                },
+                Replicated,
+            ))
+            .id();
+
+        for mut client_visibility in &mut query_client_visibility {
+            client_visibility.set_visibility(entity, true);
+        }
+    }
+}
+
+/// Replaces the local entity with the server entity once it arrives.
+fn observer_client_confirm_spawn(
+    trigger: Trigger<OnAdd, PredictedSpawn>,
+    mut commands: Commands,
+    client_context: Option<Res<ClientContext>>,
+    mut local_spawns: ResMut<LocalSpawns>,
+    query: Query<&PredictedSpawn>,
+) {
+    let Ok(spawn) = query.get(trigger.target()) else {
+        return;
+    };
+
+    if client_context.and_then(|x| x.player_id) != Some(spawn.player_id) {
+        return;
+    }
+
+    // The local entity may have expired already.
+    if let Some(mut entity) = local_spawns
+        .pending
+        .remove(&spawn.id)
+        .and_then(|x| commands.get_entity(x).ok())
+    {
+        entity.despawn();
+    }
+}
+
+fn system_client_reject_spawn(
+    mut reader: EventReader<SpawnRejected>,
+    mut commands: Commands,
+    mut local_spawns: ResMut<LocalSpawns>,
+) {
+    for rejected in reader.read() {
+        if let Some(mut entity) = local_spawns
+            .pending
+            .remove(&rejected.id)
+            .and_then(|x| commands.get_entity(x).ok())
+        {
+            entity.despawn();
+        }
+    }
+}
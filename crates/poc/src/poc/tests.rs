@@ -0,0 +1,1101 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    iter::repeat_n,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+};
+
+use avian2d::prelude::{Collider, CollisionLayers, LinearVelocity, RigidBody, SpatialQueryFilter};
+use bevy::{ecs::system::RunSystemOnce, prelude::*};
+use bevy_replicon::{server::server_tick::ServerTick, shared::replicon_tick::RepliconTick};
+
+use super::{
+    conditioner::{ConditionerConfig, Distribution, LinkConditions},
+    config::NetworkConfig,
+    lag_compensation::{ColliderHistory, ColliderPose, LagCompensation, LagCompensationConfig},
+    level::{Level, TerrainPiece, TerrainShape},
+    loopback::{headless_app, LoopbackNetwork},
+    movement::{Movement, MovementController},
+    platform::{MovingPlatform, SimulationTick},
+    projectile::{Projectile, PROJECTILE_LIFETIME, PROJECTILE_SPEED},
+    recording::{read_frames, replay, RecordEntry, Recorder},
+    remote::{RemoteBodies, RemotePoses},
+    replay_inputs, simulation_app,
+    spawn::{LocalSpawn, LocalSpawns, PredictedSpawn, SpawnRequest, Spawnable},
+    tick_duration,
+    trace::{read_trace, Tracer},
+    tuning::MovementPresets,
+    ClientMode, Discontinuity, DiscontinuityExt, Grounded, InputScript, JumpState, MovementConfig,
+    MovementInput, Player, PocType, Predicted, PredictedMemory, Reconciliation,
+    ServerMovementConfig, ServerOverrideSet, SimulationMode, WallContact,
+};
+
+const STRATEGIES: [Reconciliation; 2] = [Reconciliation::Rollback, Reconciliation::Algebraic];
+
+const TICKS: usize = 360;
+const POSITION_TOLERANCE: f32 = 1.0;
+const VELOCITY_TOLERANCE: f32 = 1.0;
+/// Ticks until the client predicts from a state the server acknowledged.
+const WARMUP_TICKS: u32 = 60;
+
+fn input(direction: f32, jump: bool) -> MovementInput {
+    MovementInput {
+        direction,
+        jump,
+        ..default()
+    }
+}
+
+/// Runs left and right with a few jumps and then stands still, so the player
+/// comes to rest on the terrain long before the session ends.
+fn script() -> InputScript {
+    let mut inputs = Vec::new();
+
+    inputs.extend(repeat_n(input(0.0, false), 30));
+    inputs.extend(repeat_n(input(1.0, false), 20));
+    inputs.extend(repeat_n(input(1.0, true), 5));
+    inputs.extend(repeat_n(input(0.5, false), 10));
+    inputs.extend(repeat_n(input(-1.0, false), 20));
+    inputs.extend(repeat_n(input(-1.0, true), 3));
+    inputs.extend(repeat_n(input(0.0, false), 20));
+    inputs.extend(repeat_n(input(0.0, true), 2));
+    inputs.push(input(0.0, false));
+
+    InputScript { inputs }
+}
+
+fn latency(milliseconds: f32, loss: f32) -> ConditionerConfig {
+    ConditionerConfig {
+        seed: Some(0),
+        default: LinkConditions {
+            latency: Distribution::Normal {
+                mean: milliseconds,
+                std_dev: milliseconds * 0.1,
+            },
+            loss,
+            ..default()
+        },
+        ..default()
+    }
+}
+
+struct PlayerState {
+    position: Vec2,
+    velocity: Vec2,
+}
+
+fn server_player(app: &mut App) -> PlayerState {
+    let mut query = app
+        .world_mut()
+        .query_filtered::<(&Transform, &LinearVelocity), With<Player>>();
+
+    let (transform, velocity) = query
+        .single(app.world())
+        .expect("server should have spawned exactly one player");
+
+    PlayerState {
+        position: transform.translation.truncate(),
+        velocity: velocity.0,
+    }
+}
+
+fn predicted_player(app: &mut App) -> PlayerState {
+    let mut query = app
+        .world_mut()
+        .query_filtered::<(&Transform, &LinearVelocity), (With<Player>, With<Predicted>)>();
+
+    let (transform, velocity) = query
+        .single(app.world())
+        .expect("client should predict its own player");
+
+    PlayerState {
+        position: transform.translation.truncate(),
+        velocity: velocity.0,
+    }
+}
+
+/// Connects a client running [`script`] to a server and runs both for `ticks`.
+/// `setup` receives the server and the client before they are connected.
+fn run_session(
+    conditioner: ConditionerConfig,
+    ticks: usize,
+    setup: impl FnOnce(&mut App, &mut App),
+) -> LoopbackNetwork {
+    let mut server = headless_app(PocType::Server, NetworkConfig::default());
+
+    let mut client = headless_app(
+        PocType::Client(ClientMode::Scripted),
+        NetworkConfig {
+            conditioner,
+            ..default()
+        },
+    );
+    client.insert_resource(script());
+
+    setup(&mut server, &mut client);
+
+    let mut loopback = LoopbackNetwork::new(server);
+    loopback.connect(client);
+    loopback.run(ticks);
+
+    loopback
+}
+
+/// File in the temp directory that is removed on drop, also when a test fails.
+struct TempPath(PathBuf);
+
+impl TempPath {
+    fn new(name: &str) -> Self {
+        Self(env::temp_dir().join(format!("poc-{}-{}", process::id(), name)))
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn assert_converged(conditioner: ConditionerConfig) {
+    for reconciliation in STRATEGIES {
+        let mut loopback = run_session(conditioner.clone(), TICKS, |_, client| {
+            client.insert_resource(reconciliation);
+        });
+        let server = server_player(&mut loopback.server);
+        let client = predicted_player(&mut loopback.clients[0].app);
+
+        assert!(
+            server.position.distance(client.position) <= POSITION_TOLERANCE,
+            "{:?}: position diverged: server {} client {}",
+            reconciliation,
+            server.position,
+            client.position
+        );
+
+        assert!(
+            server.velocity.distance(client.velocity) <= VELOCITY_TOLERANCE,
+            "{:?}: velocity diverged: server {} client {}",
+            reconciliation,
+            server.velocity,
+            client.velocity
+        );
+    }
+}
+
+#[test]
+fn prediction_converges_without_latency() {
+    assert_converged(ConditionerConfig::default());
+}
+
+#[test]
+fn prediction_converges_with_latency() {
+    assert_converged(latency(60.0, 0.0));
+}
+
+#[test]
+fn prediction_converges_with_latency_and_loss() {
+    assert_converged(latency(60.0, 0.1));
+}
+
+fn memory_len<C: Component>(app: &mut App) -> usize {
+    let world = app.world_mut();
+    world
+        .query_filtered::<&PredictedMemory<C>, With<Predicted>>()
+        .single(world)
+        .expect("client should have received acks")
+        .values
+        .len()
+}
+
+#[test]
+fn algebraic_memory_stays_bounded() {
+    let mut loopback = run_session(latency(60.0, 0.0), TICKS, |_, client| {
+        client.insert_resource(Reconciliation::Algebraic);
+    });
+    let client = &mut loopback.clients[0].app;
+
+    // Every acknowledged value is consumed in the tick it arrives.
+    assert!(memory_len::<Transform>(client) <= 2);
+    assert!(memory_len::<JumpState>(client) <= 2);
+    assert!(memory_len::<WallContact>(client) <= 2);
+}
+
+#[test]
+fn player_moved_during_session() {
+    let mut loopback = run_session(ConditionerConfig::default(), TICKS, |_, _| {});
+    let server = server_player(&mut loopback.server);
+
+    // Guards against the other tests passing because no input arrived at all.
+    assert_ne!(server.position.x, 0.0);
+}
+
+/// No terrain below the first spawn point, so the player falls through the
+/// kill plane and respawns above a floor.
+fn falling_level() -> Level {
+    Level {
+        terrain: vec![TerrainPiece {
+            position: Vec2::new(1000.0, -100.0),
+            shape: TerrainShape::Rectangle {
+                width: 500.0,
+                height: 20.0,
+            },
+        }],
+        platforms: vec![],
+        spawns: vec![Vec2::new(0.0, 100.0), Vec2::new(1000.0, 100.0)],
+        kill_plane: -300.0,
+    }
+}
+
+#[test]
+fn player_respawns_below_kill_plane() {
+    for reconciliation in STRATEGIES {
+        let mut loopback = run_session(latency(60.0, 0.0), TICKS, |server, client| {
+            server.insert_resource(falling_level());
+            client
+                .insert_resource(InputScript {
+                    inputs: vec![input(0.0, false); TICKS],
+                })
+                .insert_resource(reconciliation);
+        });
+
+        let server = server_player(&mut loopback.server);
+        let client = predicted_player(&mut loopback.clients[0].app);
+
+        assert!(
+            (server.position.x - 1000.0).abs() <= POSITION_TOLERANCE && server.position.y > -100.0,
+            "{:?}: server did not respawn the player: {}",
+            reconciliation,
+            server.position
+        );
+        assert!(
+            server.position.distance(client.position) <= POSITION_TOLERANCE,
+            "{:?}: prediction did not follow the respawn: server {} client {}",
+            reconciliation,
+            server.position,
+            client.position
+        );
+    }
+}
+
+/// Input tick at which [`system_teleport`] moves the player, shortly after it
+/// stopped running right in [`teleport_script`].
+const TELEPORT_TICK: u32 = 57;
+const TELEPORT_X: f32 = -100.0;
+
+fn teleport_script() -> InputScript {
+    let mut inputs = Vec::new();
+
+    inputs.extend(repeat_n(input(0.0, false), 30));
+    inputs.extend(repeat_n(input(1.0, false), 25));
+    inputs.extend(repeat_n(input(0.0, false), TICKS));
+
+    InputScript { inputs }
+}
+
+/// Teleports the player back to the left while it still slides to the right.
+fn system_teleport(
+    mut commands: Commands,
+    mut done: Local<bool>,
+    mut query: Query<(Entity, &Movement, &mut Transform, &mut LinearVelocity), With<Player>>,
+) {
+    for (entity, movement, mut transform, mut velocity) in &mut query {
+        if *done
+            || movement
+                .input
+                .as_ref()
+                .is_none_or(|x| x.tick.get() < TELEPORT_TICK)
+        {
+            continue;
+        }
+
+        transform.translation.x = TELEPORT_X;
+        velocity.0 = Vec2::ZERO;
+        commands.entity(entity).mark_discontinuity();
+        *done = true;
+    }
+}
+
+#[test]
+fn prediction_snaps_to_discontinuity() {
+    for reconciliation in STRATEGIES {
+        let mut loopback = run_session(latency(60.0, 0.0), 0, |server, client| {
+            server.add_systems(FixedUpdate, system_teleport.in_set(ServerOverrideSet));
+            client
+                .insert_resource(teleport_script())
+                .insert_resource(reconciliation);
+        });
+
+        let mut snapped = false;
+
+        for _ in 0..TICKS {
+            loopback.run(1);
+
+            let client = &mut loopback.clients[0].app;
+            let position = predicted_player(client).position;
+
+            if !snapped {
+                // Until then it continues the slide to the right.
+                snapped = position.x < TELEPORT_X / 2.0;
+
+                if snapped {
+                    let world = client.world_mut();
+                    let mut query = world.query_filtered::<
+                        (&Discontinuity, &PredictedMemory<Transform>),
+                        With<Predicted>,
+                    >();
+                    let (discontinuity, memory) = query
+                        .single(world)
+                        .expect("client should have received the discontinuity");
+
+                    assert!(
+                        memory
+                            .values
+                            .iter()
+                            .all(|(_, tick)| *tick >= discontinuity.tick),
+                        "{:?}: values from before the discontinuity at {:?} are left: {:?}",
+                        reconciliation,
+                        discontinuity.tick,
+                        memory.values.iter().map(|(_, x)| x).collect::<Vec<_>>()
+                    );
+                }
+            }
+
+            // Corrections from the old motion would pull the player to the right.
+            assert!(
+                !snapped || (position.x - TELEPORT_X).abs() <= POSITION_TOLERANCE,
+                "{:?}: prediction at {} after snapping to {}",
+                reconciliation,
+                position,
+                TELEPORT_X
+            );
+        }
+
+        assert!(
+            snapped,
+            "{:?}: prediction did not follow the teleport",
+            reconciliation
+        );
+
+        let server = server_player(&mut loopback.server);
+        assert!((server.position.x - TELEPORT_X).abs() <= POSITION_TOLERANCE);
+    }
+}
+
+#[test]
+fn remote_poses_interpolate_and_extrapolate() {
+    let dt = tick_duration(30.0).as_secs_f32();
+    let mut poses = RemotePoses::default();
+
+    poses.push_position(10, Vec2::new(0.0, 0.0));
+    poses.push_position(12, Vec2::new(20.0, 0.0));
+    poses.push_velocity(12, Vec2::new(300.0, 0.0));
+
+    assert_eq!(poses.position(11, dt), Some(Vec2::new(10.0, 0.0)));
+    assert_eq!(poses.position(5, dt), Some(Vec2::new(0.0, 0.0)));
+    assert!(
+        poses
+            .position(14, dt)
+            .unwrap()
+            .distance(Vec2::new(40.0, 0.0))
+            < 1e-3
+    );
+    // Extrapolation stops after a while.
+    assert_eq!(poses.position(100, dt), poses.position(1000, dt));
+}
+
+#[test]
+fn remote_players_follow_server_as_kinematic_bodies() {
+    for remote_bodies in [RemoteBodies::Interpolated, RemoteBodies::Extrapolated] {
+        let mut loopback =
+            LoopbackNetwork::new(headless_app(PocType::Server, NetworkConfig::default()));
+
+        for _ in 0..2 {
+            let mut client = headless_app(
+                PocType::Client(ClientMode::Scripted),
+                NetworkConfig {
+                    conditioner: latency(60.0, 0.0),
+                    ..default()
+                },
+            );
+            client
+                .insert_resource(script())
+                .insert_resource(remote_bodies);
+
+            loopback.connect(client);
+        }
+
+        loopback.run(TICKS);
+
+        let server = loopback
+            .server
+            .world_mut()
+            .query::<(&Player, &Transform)>()
+            .iter(loopback.server.world())
+            .map(|(player, transform)| (player.player_id, transform.translation.truncate()))
+            .collect::<HashMap<_, _>>();
+
+        let client = loopback.clients[0].app.world_mut();
+        let mut query =
+            client.query_filtered::<(&Player, &Transform, &RigidBody), Without<Predicted>>();
+        let (player, transform, body) = query
+            .single(client)
+            .expect("client should see the other player");
+
+        assert!(body.is_kinematic(), "{:?}: {:?}", remote_bodies, body);
+        assert!(
+            transform
+                .translation
+                .truncate()
+                .distance(server[&player.player_id])
+                <= POSITION_TOLERANCE,
+            "{:?}: remote player at {} server {}",
+            remote_bodies,
+            transform.translation,
+            server[&player.player_id]
+        );
+    }
+}
+
+#[test]
+fn server_confirms_or_rejects_predicted_projectiles() {
+    let mut loopback = run_session(latency(60.0, 0.0), WARMUP_TICKS as usize, |_, _| {});
+
+    loopback.clients[0]
+        .app
+        .world_mut()
+        .run_system_once(
+            |mut commands: Commands,
+             mut local_spawns: ResMut<LocalSpawns>,
+             mut writer: EventWriter<SpawnRequest<Projectile>>,
+             tick: Res<SimulationTick>,
+             query: Query<&Transform, With<Predicted>>| {
+                let origin = query.single().unwrap().translation.truncate();
+
+                for origin in [origin, origin + Vec2::new(1000.0, 0.0)] {
+                    local_spawns.spawn(
+                        &mut commands,
+                        &mut writer,
+                        Projectile {
+                            origin,
+                            velocity: Vec2::new(PROJECTILE_SPEED, 0.0),
+                            tick: tick.0,
+                        },
+                    );
+                }
+            },
+        )
+        .unwrap();
+
+    loopback.run(30);
+
+    let server = loopback.server.world_mut();
+    let projectiles = server
+        .query::<(&Projectile, &Transform)>()
+        .iter(server)
+        .map(|(projectile, transform)| (projectile.origin, transform.translation.truncate()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(projectiles.len(), 1);
+    let (origin, position) = projectiles[0];
+    assert!(
+        position.x > origin.x,
+        "projectile at {} fired from {}",
+        position,
+        origin
+    );
+
+    let client = loopback.clients[0].app.world_mut();
+    assert_eq!(client.resource::<LocalSpawns>().pending(), 0);
+    assert_eq!(
+        client
+            .query_filtered::<(), With<LocalSpawn>>()
+            .iter(client)
+            .count(),
+        0,
+        "local projectiles should be replaced or removed"
+    );
+    assert_eq!(
+        client
+            .query_filtered::<(), (With<Projectile>, With<PredictedSpawn>)>()
+            .iter(client)
+            .count(),
+        1
+    );
+
+    loopback.run(PROJECTILE_LIFETIME as usize);
+
+    for app in [&mut loopback.server, &mut loopback.clients[0].app] {
+        let world = app.world_mut();
+        assert_eq!(
+            world
+                .query_filtered::<(), With<Projectile>>()
+                .iter(world)
+                .count(),
+            0,
+            "expired projectiles should be despawned"
+        );
+    }
+}
+
+#[test]
+fn projectile_is_checked_against_pose_at_its_tick() {
+    let pose = |tick, x| ColliderPose {
+        tick,
+        transform: Transform::from_xyz(x, 0.0, 0.0),
+        collider: Collider::circle(10.0),
+        layers: CollisionLayers::default(),
+    };
+    let history = ColliderHistory {
+        poses: [pose(10, 0.0), pose(20, 500.0)].into(),
+    };
+    let projectile = |x, tick| Projectile {
+        origin: Vec2::new(x, 0.0),
+        velocity: Vec2::new(PROJECTILE_SPEED, 0.0),
+        tick,
+    };
+
+    assert!(projectile(0.0, 15).accept(&history, 20));
+    assert!(!projectile(500.0, 15).accept(&history, 20));
+    // Ticks the server did not simulate yet use the newest pose.
+    assert!(projectile(500.0, 22).accept(&history, 20));
+    // Before the oldest pose.
+    assert!(!projectile(0.0, 5).accept(&history, 20));
+}
+
+#[test]
+fn lag_compensation_rewinds_client_that_joined_late() {
+    let config = LagCompensationConfig {
+        max_rewind_ticks: 20,
+        interpolation_ticks: 10,
+    };
+
+    let mut server = headless_app(PocType::Server, NetworkConfig::default());
+    server.insert_resource(config);
+
+    let mut loopback = LoopbackNetwork::new(server);
+    // Client ticks start at the login, far behind the server tick.
+    loopback.run(300);
+
+    let mut client = headless_app(
+        PocType::Client(ClientMode::Scripted),
+        NetworkConfig {
+            conditioner: latency(60.0, 0.0),
+            ..default()
+        },
+    );
+    // Keeps running, so the rewound pose is well behind the current one.
+    let mut inputs = vec![input(0.0, false); 30];
+    inputs.extend(repeat_n(input(1.0, false), TICKS));
+    client.insert_resource(InputScript { inputs });
+    loopback.connect(client);
+    loopback.run(WARMUP_TICKS as usize);
+
+    loopback
+        .server
+        .world_mut()
+        .run_system_once(
+            move |lag_compensation: LagCompensation,
+                  server_tick: Res<ServerTick>,
+                  query: Query<
+                (Entity, &Movement, &Transform, &ColliderHistory),
+                With<Player>,
+            >| {
+                let (entity, movement, transform, history) = query.single().unwrap();
+                assert!(history.poses.len() as u32 <= config.max_rewind_ticks + 2);
+
+                // The input was simulated this tick and progressed afterwards.
+                let input_tick = movement.input.as_ref().unwrap().tick.get() - 1;
+                let tick = lag_compensation
+                    .rewind_tick(entity, RepliconTick::new(input_tick))
+                    .unwrap();
+
+                assert_eq!(tick, server_tick.get() - config.interpolation_ticks);
+
+                let old = history.at(tick).unwrap().transform.translation.truncate();
+                let current = transform.translation.truncate();
+                assert!(current.x - old.x > 40.0, "{old} {current}");
+
+                // Rays straight down hit the player at the rewound pose and
+                // the terrain below its current one.
+                let pipeline = lag_compensation.rewind(tick);
+                let hit = |position: Vec2| {
+                    pipeline
+                        .cast_ray(
+                            position + Vec2::new(0.0, 200.0),
+                            Dir2::NEG_Y,
+                            400.0,
+                            true,
+                            &SpatialQueryFilter::default(),
+                        )
+                        .map(|x| x.entity)
+                };
+
+                assert_eq!(hit(old), Some(entity));
+                assert_ne!(hit(current), Some(entity));
+            },
+        )
+        .unwrap();
+}
+
+#[test]
+fn replay_reproduces_recorded_session() {
+    for simulation in [SimulationMode::Standard, SimulationMode::Deterministic] {
+        let path = TempPath::new(&format!("replay-{:?}.rec", simulation));
+
+        let loopback = run_session(ConditionerConfig::default(), TICKS, |server, client| {
+            server
+                .insert_resource(Recorder::create(&path, 10).unwrap())
+                .insert_resource(simulation);
+            client.insert_resource(simulation);
+        });
+
+        // Dropping the apps flushes the recording.
+        drop(loopback);
+
+        let report = replay(&path, POSITION_TOLERANCE).unwrap();
+
+        assert!(report.snapshots > 1, "{:?}", simulation);
+        assert_eq!(
+            report.first_divergence, None,
+            "{:?}: {:?}",
+            simulation, report
+        );
+    }
+}
+
+#[test]
+fn replay_applies_reloaded_movement_config() {
+    let path = TempPath::new("replay-config.rec");
+
+    let mut loopback = run_session(
+        ConditionerConfig::default(),
+        WARMUP_TICKS as usize,
+        |server, _| {
+            server.insert_resource(Recorder::create(&path, 10).unwrap());
+        },
+    );
+
+    // Like a hot reload of the presets while the player is running.
+    let world = loopback.server.world_mut();
+    for mut config in world.query::<&mut MovementConfig>().iter_mut(world) {
+        config.acceleration *= 3.0;
+        config.max_velocity *= 2.0;
+    }
+
+    loopback.run(TICKS);
+    drop(loopback);
+
+    let report = replay(&path, POSITION_TOLERANCE).unwrap();
+
+    assert_eq!(report.first_divergence, None, "{:?}", report);
+}
+
+#[test]
+fn oversized_frame_is_rejected() {
+    let path = TempPath::new("oversized.rec");
+    fs::write(&*path, u32::MAX.to_le_bytes()).unwrap();
+
+    let err = format!("{:#}", read_frames::<RecordEntry>(&path).err().unwrap());
+
+    assert!(err.contains("exceeds"), "{}", err);
+}
+
+#[test]
+fn trace_contains_predictions_and_acks() {
+    let path = TempPath::new("trace.trace");
+
+    let loopback = run_session(ConditionerConfig::default(), TICKS, |_, client| {
+        client.insert_resource(Tracer::create(&path).unwrap());
+    });
+    drop(loopback);
+
+    let trace = read_trace(&path).unwrap();
+
+    assert!(!trace.ticks.is_empty());
+    assert!(trace.ticks.iter().any(|x| !x.acks.is_empty()));
+    assert!(trace.ticks.iter().any(|x| x.replayed > 0));
+}
+
+#[test]
+fn deterministic_prediction_matches_server_bit_for_bit() {
+    let path = TempPath::new("deterministic.trace");
+
+    let loopback = run_session(ConditionerConfig::default(), TICKS, |server, client| {
+        server.insert_resource(SimulationMode::Deterministic);
+        client
+            .insert_resource(SimulationMode::Deterministic)
+            .insert_resource(Tracer::create(&path).unwrap());
+    });
+    drop(loopback);
+
+    let trace = read_trace(&path).unwrap();
+
+    let predicted = trace
+        .ticks
+        .iter()
+        .map(|x| (x.tick, x.predicted))
+        .collect::<HashMap<_, _>>();
+
+    let mut compared = 0;
+
+    for ack in trace.ticks.iter().flat_map(|x| &x.acks) {
+        let Some(state) = predicted
+            .get(&ack.tick)
+            .filter(|_| ack.tick >= WARMUP_TICKS)
+        else {
+            continue;
+        };
+
+        assert_eq!(
+            state.position.to_array().map(f32::to_bits),
+            ack.position.to_array().map(f32::to_bits),
+            "position differs at tick {}: predicted {} server {}",
+            ack.tick,
+            state.position,
+            ack.position
+        );
+
+        if let Some(velocity) = ack.velocity {
+            assert_eq!(
+                state.velocity.to_array().map(f32::to_bits),
+                velocity.to_array().map(f32::to_bits),
+                "velocity differs at tick {}: predicted {} server {}",
+                ack.tick,
+                state.velocity,
+                velocity
+            );
+        }
+
+        compared += 1;
+    }
+
+    assert!(compared > 0);
+}
+
+#[test]
+fn client_predicts_with_server_movement_config() {
+    let config = MovementConfig {
+        acceleration: 1500.0,
+        max_velocity: 400.0,
+        ..default()
+    };
+
+    let mut loopback = run_session(ConditionerConfig::default(), TICKS, |server, _| {
+        server.insert_resource(ServerMovementConfig(config.clone()));
+    });
+
+    let mut query = loopback.clients[0]
+        .app
+        .world_mut()
+        .query_filtered::<&MovementConfig, With<Predicted>>();
+    let predicted = query
+        .single(loopback.clients[0].app.world())
+        .expect("client should predict its own player");
+
+    assert_eq!(*predicted, config);
+
+    let server = server_player(&mut loopback.server);
+    let client = predicted_player(&mut loopback.clients[0].app);
+
+    assert!(server.position.distance(client.position) <= POSITION_TOLERANCE);
+}
+
+#[test]
+fn default_movement_presets_parse() {
+    let presets: MovementPresets =
+        ron::from_str(include_str!("../../assets/default.movement.ron")).unwrap();
+
+    assert!(presets.presets.contains_key("default"));
+}
+
+#[test]
+fn example_level_parses() {
+    let level: Level = ron::from_str(include_str!("../../assets/levels/slopes.ron")).unwrap();
+
+    assert!(!level.spawns.is_empty());
+    for piece in &level.terrain {
+        piece.shape.validate().unwrap();
+    }
+}
+
+#[test]
+fn level_with_degenerate_polygon_is_rejected() {
+    let path = TempPath::new("level.ron");
+    let level = Level {
+        terrain: vec![
+            TerrainPiece {
+                position: Vec2::ZERO,
+                shape: TerrainShape::Rectangle {
+                    width: 100.0,
+                    height: 10.0,
+                },
+            },
+            TerrainPiece {
+                position: Vec2::ZERO,
+                shape: TerrainShape::Polygon {
+                    vertices: vec![Vec2::ZERO, Vec2::X],
+                },
+            },
+        ],
+        ..default()
+    };
+    fs::write(&*path, ron::to_string(&level).unwrap()).unwrap();
+
+    let err = format!("{:#}", Level::from_file(&path).unwrap_err());
+
+    assert!(err.contains(&path.display().to_string()), "{}", err);
+    assert!(err.contains("shape 1"), "{}", err);
+}
+
+#[test]
+fn level_without_spawns_is_rejected() {
+    let path = TempPath::new("no-spawns.ron");
+    let level = Level {
+        spawns: vec![],
+        ..default()
+    };
+    fs::write(&*path, ron::to_string(&level).unwrap()).unwrap();
+
+    let err = format!("{:#}", Level::from_file(&path).unwrap_err());
+
+    assert!(err.contains("spawn"), "{}", err);
+}
+
+/// Whether a player that dropped onto a slope of `degrees` is grounded.
+fn grounded_on_slope(degrees: f32) -> bool {
+    let mut app = simulation_app(Vec2::new(0.0, -1000.0));
+    let world = app.world_mut();
+
+    let height = 1000.0 * degrees.to_radians().tan();
+    let slope = TerrainPiece {
+        position: Vec2::ZERO,
+        shape: TerrainShape::Polygon {
+            vertices: vec![
+                Vec2::new(-500.0, 0.0),
+                Vec2::new(500.0, 0.0),
+                Vec2::new(500.0, height),
+            ],
+        },
+    };
+    world.spawn(slope.bundle());
+
+    let player = world
+        .spawn((
+            MovementController::new(Collider::capsule(10.0, 30.0), MovementConfig::default()),
+            Transform::from_xyz(0.0, height / 2.0 + 40.0, 0.0),
+        ))
+        .id();
+
+    replay_inputs(
+        world,
+        &[player],
+        &vec![input(0.0, false); 20],
+        tick_duration(30.0),
+        0,
+    );
+
+    world.get::<Grounded>(player).unwrap().get()
+}
+
+#[test]
+fn max_slope_angle_limits_grounded_slopes() {
+    // The default config allows slopes up to 30 degrees.
+    assert!(grounded_on_slope(20.0));
+    assert!(!grounded_on_slope(45.0));
+}
+
+/// Simulation with a player standing on a wide terrain.
+fn standing_player() -> (App, Entity) {
+    let mut app = simulation_app(Vec2::new(0.0, -1000.0));
+    let world = app.world_mut();
+
+    world.spawn((
+        Transform::from_xyz(0.0, -100.0, 0.0),
+        RigidBody::Static,
+        Collider::rectangle(5000.0, 20.0),
+    ));
+
+    let player = world
+        .spawn((
+            MovementController::new(Collider::capsule(10.0, 30.0), MovementConfig::default()),
+            Transform::from_xyz(0.0, -65.0, 0.0),
+        ))
+        .id();
+
+    // Lets the player settle, so it is grounded from the first tick.
+    replay_inputs(
+        world,
+        &[player],
+        &vec![input(0.0, false); 10],
+        tick_duration(30.0),
+        0,
+    );
+
+    (app, player)
+}
+
+/// Horizontal velocity of a player that slides over the terrain for one second
+/// without input, after starting at 200.
+fn damped_velocity(hz: f32) -> f32 {
+    let (mut app, player) = standing_player();
+    let world = app.world_mut();
+
+    let dt = tick_duration(hz);
+    let inputs = vec![MovementInput::default(); hz as usize];
+
+    world
+        .entity_mut(player)
+        .insert(LinearVelocity(Vec2::new(200.0, 0.0)));
+    replay_inputs(world, &[player], &inputs, dt, 0);
+
+    world.get::<LinearVelocity>(player).unwrap().x
+}
+
+#[test]
+fn damping_does_not_depend_on_tick_rate() {
+    let slow = damped_velocity(30.0);
+    let fast = damped_velocity(60.0);
+
+    assert!(slow < 100.0, "velocity was not damped: {}", slow);
+    assert!(
+        (slow - fast).abs() <= slow * 0.01,
+        "30 Hz: {} 60 Hz: {}",
+        slow,
+        fast
+    );
+}
+
+#[test]
+fn air_jump_needs_a_new_press() {
+    let (mut app, player) = standing_player();
+    let world = app.world_mut();
+    let dt = tick_duration(30.0);
+
+    let step = |world: &mut World, jump: bool, ticks: usize| {
+        replay_inputs(world, &[player], &vec![input(0.0, jump); ticks], dt, 0);
+
+        let jump = world.get::<JumpState>(player).unwrap().clone();
+        (world.get::<LinearVelocity>(player).unwrap().y, jump)
+    };
+
+    let (velocity, _) = step(world, true, 1);
+    assert!(velocity > 0.0, "did not jump: {}", velocity);
+
+    // Holding jump does not use the air jump.
+    let (_, jump) = step(world, true, 5);
+    assert_eq!(jump.air_jumps, 1);
+
+    let (before, _) = step(world, false, 1);
+    let (after, jump) = step(world, true, 1);
+    assert!(
+        after > before,
+        "did not jump in the air: {} {}",
+        before,
+        after
+    );
+    assert_eq!(jump.air_jumps, 0);
+
+    step(world, false, 1);
+    let (before, _) = step(world, false, 1);
+    let (after, _) = step(world, true, 1);
+    assert!(
+        after < before,
+        "jumped without air jumps left: {} {}",
+        before,
+        after
+    );
+}
+
+#[test]
+fn player_slides_down_and_jumps_off_walls() {
+    let (mut app, player) = standing_player();
+    let world = app.world_mut();
+    let dt = tick_duration(30.0);
+
+    // The left face of the wall is at x = 30, the player is one unit away.
+    world.spawn((
+        Transform::from_xyz(40.0, 200.0, 0.0),
+        RigidBody::Static,
+        Collider::rectangle(20.0, 600.0),
+    ));
+    world
+        .entity_mut(player)
+        .insert(Transform::from_xyz(19.0, 300.0, 0.0));
+
+    replay_inputs(world, &[player], &vec![input(1.0, false); 20], dt, 0);
+
+    assert_eq!(world.get::<WallContact>(player), Some(&WallContact::Right));
+
+    // Free fall would be at -666 after 20 ticks.
+    let velocity = world.get::<LinearVelocity>(player).unwrap().0;
+    assert!(velocity.y > -300.0, "did not slide: {}", velocity);
+
+    replay_inputs(world, &[player], &[input(1.0, true)], dt, 0);
+
+    let velocity = world.get::<LinearVelocity>(player).unwrap().0;
+    assert!(
+        velocity.x < 0.0 && velocity.y > 0.0,
+        "did not jump off the wall: {}",
+        velocity
+    );
+}
+
+#[test]
+fn player_rides_moving_platform() {
+    let mut app = simulation_app(Vec2::new(0.0, -1000.0));
+    let world = app.world_mut();
+
+    let platform = MovingPlatform {
+        start: Vec2::new(0.0, -100.0),
+        end: Vec2::new(200.0, -100.0),
+        period: 60,
+    };
+
+    world.spawn((
+        Transform::from_translation(platform.start.extend(0.0)),
+        RigidBody::Kinematic,
+        Collider::rectangle(100.0, 20.0),
+        platform.clone(),
+    ));
+
+    let player = world
+        .spawn((
+            MovementController::new(Collider::capsule(10.0, 30.0), MovementConfig::default()),
+            Transform::from_xyz(0.0, -65.0, 0.0),
+        ))
+        .id();
+
+    let inputs = (1..=40)
+        .map(|tick| MovementInput {
+            tick: RepliconTick::new(tick),
+            ..default()
+        })
+        .collect::<Vec<_>>();
+
+    replay_inputs(world, &[player], &inputs, tick_duration(30.0), 0);
+
+    let position = world.get::<Transform>(player).unwrap().translation;
+
+    // The player only rides along once it is grounded after the first tick.
+    assert!(
+        (position.x - platform.position(40).x).abs() < 5.0,
+        "player at {} platform at {}",
+        position,
+        platform.position(40)
+    );
+    assert!(
+        position.y > -100.0,
+        "fell through the platform: {}",
+        position
+    );
+}
@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    mem,
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+use avian2d::prelude::LinearVelocity;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    fixed_hz,
+    movement::Movement,
+    recording::{read_frames, replay_with, write_frame},
+    ClientContext, MovementInput, Predicted, PredictedMemory, Reconciliation,
+};
+
+/// Number of ticks after which the trace is flushed to disk.
+const FLUSH_INTERVAL: u32 = 32;
+
+/// One entry of a client trace.
+#[derive(Serialize, Deserialize)]
+pub enum TraceEntry {
+    /// Written once the client knows its player.
+    Header {
+        player_id: u64,
+        reconciliation: Reconciliation,
+        hz: f32,
+    },
+    Tick(TickTrace),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TickTrace {
+    /// Client tick, equal to the tick of the input.
+    pub tick: u32,
+    pub input: Option<MovementInput>,
+    /// State of the player after simulating this tick.
+    pub predicted: TracedState,
+    /// Server states that arrived since the previous tick.
+    pub acks: Vec<TracedAck>,
+    /// How much the reconciliation moved the player before simulating this tick.
+    pub correction: TracedState,
+    /// Number of inputs replayed by the rollback before simulating this tick.
+    pub replayed: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug)]
+pub struct TracedState {
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+impl TracedState {
+    fn new(transform: &Transform, linear_velocity: &LinearVelocity) -> Self {
+        Self {
+            position: transform.translation.truncate(),
+            velocity: linear_velocity.0,
+        }
+    }
+}
+
+/// Authoritative state of the player for the client tick `tick`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct TracedAck {
+    pub tick: u32,
+    pub position: Vec2,
+    /// Missing if the velocity was not acknowledged for the same tick.
+    pub velocity: Option<Vec2>,
+}
+
+/// Traces the prediction of the client if inserted as a resource.
+#[derive(Resource)]
+pub struct Tracer {
+    writer: BufWriter<File>,
+    header_written: bool,
+    last_ack: u32,
+    last_predicted: Option<TracedState>,
+    acks: Vec<TracedAck>,
+    correction: TracedState,
+    pub(super) replayed: u32,
+}
+
+impl Tracer {
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            header_written: false,
+            last_ack: 0,
+            last_predicted: None,
+            acks: Vec::new(),
+            correction: TracedState::default(),
+            replayed: 0,
+        })
+    }
+
+    fn write(&mut self, entry: &TraceEntry) {
+        if let Err(err) = write_frame(&mut self.writer, entry) {
+            println!("Failed to trace: {}", err);
+        }
+    }
+}
+
+/// Collects acknowledged states right after replication, before the
+/// reconciliation consumes them.
+pub(super) fn system_trace_acks(
+    mut tracer: ResMut<Tracer>,
+    query: Query<
+        (
+            &PredictedMemory<Transform>,
+            Option<&PredictedMemory<LinearVelocity>>,
+        ),
+        With<Predicted>,
+    >,
+) {
+    for (transforms, velocities) in &query {
+        for (transform, tick) in &transforms.values {
+            if tick.get() <= tracer.last_ack {
+                continue;
+            }
+
+            let velocity = velocities
+                .and_then(|x| x.values.iter().find(|(_, x)| x == tick))
+                .map(|(velocity, _)| velocity.0);
+
+            tracer.last_ack = tick.get();
+            tracer.acks.push(TracedAck {
+                tick: tick.get(),
+                position: transform.translation.truncate(),
+                velocity,
+            });
+        }
+    }
+}
+
+pub(super) fn system_trace_correction(
+    mut tracer: ResMut<Tracer>,
+    client_context: Res<ClientContext>,
+    query: Query<(&Transform, &LinearVelocity)>,
+) {
+    let Some(player_entity) = client_context.player_entity else {
+        return;
+    };
+
+    let (Ok((transform, linear_velocity)), Some(last)) =
+        (query.get(player_entity), tracer.last_predicted)
+    else {
+        return;
+    };
+
+    let state = TracedState::new(transform, linear_velocity);
+
+    tracer.correction = TracedState {
+        position: state.position - last.position,
+        velocity: state.velocity - last.velocity,
+    };
+}
+
+pub(super) fn system_trace_tick(
+    mut tracer: ResMut<Tracer>,
+    client_context: Res<ClientContext>,
+    reconciliation: Res<Reconciliation>,
+    time: Res<Time<Fixed>>,
+    query: Query<(&Transform, &LinearVelocity, &Movement)>,
+) {
+    let (Some(player_entity), Some(player_id)) =
+        (client_context.player_entity, client_context.player_id)
+    else {
+        return;
+    };
+
+    let Ok((transform, linear_velocity, movement)) = query.get(player_entity) else {
+        return;
+    };
+
+    if !tracer.header_written {
+        tracer.header_written = true;
+        tracer.write(&TraceEntry::Header {
+            player_id,
+            reconciliation: *reconciliation,
+            hz: fixed_hz(&time),
+        });
+    }
+
+    let predicted = TracedState::new(transform, linear_velocity);
+    let tick = client_context.tick.get();
+
+    let entry = TraceEntry::Tick(TickTrace {
+        tick,
+        input: movement.input.clone(),
+        predicted,
+        acks: mem::take(&mut tracer.acks),
+        correction: mem::take(&mut tracer.correction),
+        replayed: mem::take(&mut tracer.replayed),
+    });
+
+    tracer.last_predicted = Some(predicted);
+    tracer.write(&entry);
+
+    if tick % FLUSH_INTERVAL == 0 {
+        if let Err(err) = tracer.writer.flush() {
+            println!("Failed to flush trace: {}", err);
+        }
+    }
+}
+
+pub struct Trace {
+    pub player_id: u64,
+    pub reconciliation: Reconciliation,
+    pub hz: f32,
+    pub ticks: Vec<TickTrace>,
+}
+
+/// Reads the header and all ticks of a client trace.
+pub fn read_trace(path: &Path) -> Result<Trace> {
+    let mut header = None;
+    let mut ticks = Vec::new();
+
+    for entry in read_frames::<TraceEntry>(path)? {
+        match entry {
+            TraceEntry::Header {
+                player_id,
+                reconciliation,
+                hz,
+            } => header = Some((player_id, reconciliation, hz)),
+            TraceEntry::Tick(tick) => ticks.push(tick),
+        }
+    }
+
+    let Some((player_id, reconciliation, hz)) = header else {
+        bail!("{} contains no header", path.display());
+    };
+
+    Ok(Trace {
+        player_id,
+        reconciliation,
+        hz,
+        ticks,
+    })
+}
+
+/// Replays the server recording and prints the predicted state of every client
+/// tick next to the state the server simulated for the same input tick.
+pub fn align(trace: &Path, recording: &Path, tolerance: f32) -> Result<()> {
+    let Trace {
+        player_id,
+        reconciliation,
+        ticks,
+        ..
+    } = read_trace(trace)?;
+
+    let mut server = HashMap::new();
+
+    // The replay is only used to get the server state of every tick.
+    replay_with(recording, f32::INFINITY, |_, players| {
+        for player in players.iter().filter(|x| x.player_id == player_id) {
+            if let Some(input) = &player.input {
+                server.insert(input.tick.get(), (input.clone(), player.position));
+            }
+        }
+    })?;
+
+    println!("Player {} using {:?}", player_id, reconciliation);
+    println!(
+        "{:>6} {:>12} {:>12} {:>22} {:>22} {:>8} {:>8} {:>4}",
+        "tick", "input", "server input", "predicted", "server", "error", "fix", "acks"
+    );
+
+    let mut first_divergence = None;
+
+    for tick in &ticks {
+        let Some((server_input, server_position)) = server.get(&tick.tick) else {
+            continue;
+        };
+
+        let error = tick.predicted.position.distance(*server_position);
+        let input_differs = tick.input.as_ref().is_none_or(|input| {
+            input.direction != server_input.direction || input.jump != server_input.jump
+        });
+
+        let marker = if error > tolerance {
+            first_divergence.get_or_insert(tick.tick);
+            "<"
+        } else {
+            ""
+        };
+
+        println!(
+            "{:>6} {:>12} {:>12} {:>22} {:>22} {:>8.3} {:>8.3} {:>4} {}{}",
+            tick.tick,
+            format_input(tick.input.as_ref()),
+            format_input(Some(server_input)),
+            format!("{:.2}", tick.predicted.position),
+            format!("{:.2}", server_position),
+            error,
+            tick.correction.position.length(),
+            tick.acks.len(),
+            marker,
+            if input_differs { " input differs" } else { "" }
+        );
+    }
+
+    match first_divergence {
+        Some(tick) => println!("Prediction first diverged at tick {}", tick),
+        None => println!("Prediction never diverged by more than {}", tolerance),
+    }
+
+    Ok(())
+}
+
+fn format_input(input: Option<&MovementInput>) -> String {
+    match input {
+        Some(input) => format!(
+            "{:.2}{}",
+            input.direction,
+            if input.jump { " jump" } else { "" }
+        ),
+        None => "-".to_string(),
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::Deserialize;
+
+use super::{MovementConfig, ServerMovementConfig};
+
+/// Named [`MovementConfig`]s, loaded from `*.movement.ron` files.
+#[derive(Asset, TypePath, Deserialize)]
+pub struct MovementPresets {
+    pub presets: HashMap<String, MovementConfig>,
+}
+
+#[derive(Default)]
+struct MovementPresetsLoader;
+
+impl AssetLoader for MovementPresetsLoader {
+    type Asset = MovementPresets;
+    type Settings = ();
+    type Error = anyhow::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<MovementPresets, anyhow::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["movement.ron"]
+    }
+}
+
+/// Makes the server use `preset` from the presets asset at `path` for all
+/// players. The preset is applied again whenever the file changes, clients
+/// receive the new values through replication.
+#[derive(Resource, Clone)]
+pub struct MovementTuning {
+    pub path: String,
+    pub preset: String,
+}
+
+#[derive(Resource)]
+struct MovementPresetsHandle(Handle<MovementPresets>);
+
+pub(super) struct TuningPlugin;
+
+impl Plugin for TuningPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<MovementPresets>()
+            .init_asset_loader::<MovementPresetsLoader>()
+            .add_systems(
+                Startup,
+                system_load_presets.run_if(resource_exists::<MovementTuning>),
+            )
+            .add_systems(
+                Update,
+                system_apply_preset.run_if(resource_exists::<MovementPresetsHandle>),
+            );
+    }
+}
+
+fn system_load_presets(
+    mut commands: Commands,
+    tuning: Res<MovementTuning>,
+    asset_server: Res<AssetServer>,
+) {
+    commands.insert_resource(MovementPresetsHandle(asset_server.load(&tuning.path)));
+}
+
+fn system_apply_preset(
+    mut reader: EventReader<AssetEvent<MovementPresets>>,
+    handle: Res<MovementPresetsHandle>,
+    presets: Res<Assets<MovementPresets>>,
+    tuning: Res<MovementTuning>,
+    mut server_config: ResMut<ServerMovementConfig>,
+    mut query: Query<&mut MovementConfig>,
+) {
+    for event in reader.read() {
+        if !event.is_loaded_with_dependencies(&handle.0) && !event.is_modified(&handle.0) {
+            continue;
+        }
+
+        let Some(presets) = presets.get(&handle.0) else {
+            continue;
+        };
+
+        let Some(config) = presets.presets.get(&tuning.preset) else {
+            println!(
+                "Movement preset {} not found in {}",
+                tuning.preset, tuning.path
+            );
+            continue;
+        };
+
+        println!("Applying movement preset {}: {:?}", tuning.preset, config);
+
+        server_config.0 = config.clone();
+
+        for mut player_config in &mut query {
+            player_config.set_if_neq(config.clone());
+        }
+    }
+}